@@ -1,44 +1,27 @@
 use std::{thread, time::Duration};
 
-use ream::{DataObject, Instruction, PID, Reg, VM};
+use ream::{Bif, DataObject, Instruction, Reg, Source, VM};
 
 fn main() {
     let vm = VM::new();
-    let proc1 = vec![
+    let proc2 = vec![
         Instruction::Move {
             dest: Reg::X(0),
-            src: DataObject::Small(0),
+            src: DataObject::atom("main"),
         },
         Instruction::Move {
             dest: Reg::X(1),
-            src: DataObject::Small(8001),
+            src: DataObject::atom("count"),
         },
         Instruction::Move {
             dest: Reg::X(2),
-            src: DataObject::Small(1),
+            src: DataObject::Nil,
         },
-        Instruction::Call { ip: 5 },
-        Instruction::Ret,
-        // Function that increments X0
-        Instruction::Add {
-            arg0: Reg::X(0),
-            arg1: Reg::X(2),
+        Instruction::Bif {
+            bif: Bif::Spawn,
+            args: vec![Reg::X(0), Reg::X(1), Reg::X(2)],
             ret: Reg::X(0),
         },
-        Instruction::IsEq {
-            lbl: 8,
-            arg0: Reg::X(0),
-            arg1: Reg::X(1),
-        },
-        Instruction::Call { ip: 5 },
-        Instruction::Ret,
-    ];
-    let proc2 = vec![
-        Instruction::Spawn { instrs: proc1 },
-        Instruction::Move {
-            dest: Reg::X(0),
-            src: DataObject::Pid(PID::new(0, 1)),
-        },
         Instruction::Move {
             dest: Reg::X(1),
             src: DataObject::Nil,
@@ -56,7 +39,7 @@ fn main() {
             dest: Reg::Y(2),
             src: DataObject::Small(1),
         },
-        Instruction::Call { ip: 9 },
+        Instruction::Call { ip: 11 },
         Instruction::Ret,
         // Function that increments Y0
         Instruction::Add {
@@ -65,16 +48,56 @@ fn main() {
             ret: Reg::Y(0),
         },
         Instruction::IsEq {
-            lbl: 12,
+            lbl: 14,
             arg0: Reg::Y(0),
             arg1: Reg::Y(1),
         },
-        Instruction::Call { ip: 9 },
+        Instruction::Call { ip: 11 },
+        Instruction::Ret,
+        // main:count/0, run by the spawned process
+        Instruction::FuncInfo {
+            module: "main".to_string(),
+            function: "count".to_string(),
+            arity: 0,
+        },
+        Instruction::Move {
+            dest: Reg::X(0),
+            src: DataObject::Small(0),
+        },
+        Instruction::Move {
+            dest: Reg::X(1),
+            src: DataObject::Small(8001),
+        },
+        Instruction::Move {
+            dest: Reg::X(2),
+            src: DataObject::Small(1),
+        },
+        Instruction::Call { ip: 21 },
+        Instruction::Ret,
+        // Function that increments X0
+        Instruction::Add {
+            arg0: Reg::X(0),
+            arg1: Reg::X(2),
+            ret: Reg::X(0),
+        },
+        Instruction::IsEq {
+            lbl: 24,
+            arg0: Reg::X(0),
+            arg1: Reg::X(1),
+        },
+        Instruction::Call { ip: 21 },
         Instruction::Ret,
     ];
-    // vm.spawn(proc1);
-    // thread::sleep(Duration::from_millis(5));
-    vm.lock().unwrap().spawn(proc2);
+    let main = vm
+        .lock()
+        .unwrap()
+        .load(Source {
+            name: "main".to_string(),
+            exports: vec![("count".to_string(), 0)],
+            instrs: proc2,
+        })
+        .unwrap();
+    vm.lock().unwrap().spawn(main);
     thread::sleep(Duration::from_millis(500));
 
     vm.lock().unwrap().wait();
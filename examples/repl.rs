@@ -1,6 +1,6 @@
 use std::{thread, time::Duration};
 
-use ream::{VM, parse_str};
+use ream::{Module, VM, parse_str};
 
 fn main() {
     let vm = VM::new();
@@ -32,9 +32,9 @@ fn main() {
 {label, 2}.
 {ret}.",
     );
-    vm.lock().unwrap().spawn(proc1);
+    vm.lock().unwrap().spawn(Module::new("proc1", proc1));
     // thread::sleep(Duration::from_millis(5));
-    vm.lock().unwrap().spawn(proc2);
+    vm.lock().unwrap().spawn(Module::new("proc2", proc2));
     thread::sleep(Duration::from_millis(500));
 
     vm.lock().unwrap().wait();
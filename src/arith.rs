@@ -0,0 +1,366 @@
+//! Arithmetic. Integers are smalls as long as they fit in one and bignums otherwise, so like in
+//! Erlang they never overflow. Operations on an integer and a float convert the integer.
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::mem::{
+    DataObject,
+    term::{Tag, Term},
+};
+
+/// Largest bignum in 64 bit digits, like `BIG_ARITY_MAX` in ERTS
+const MAX_BIG_DIGITS: u64 = (1 << 19) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    /// Integer division, rounding towards zero
+    Div,
+    /// Remainder of `Div`, which has the sign of the dividend
+    Rem,
+    Band,
+    Bor,
+    Bxor,
+    Bsl,
+    Bsr,
+    /// Division that always gives a float, `/`
+    FDiv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    /// Unary minus
+    Neg,
+    Abs,
+    Bnot,
+}
+
+fn badarith() -> DataObject {
+    DataObject::atom("badarith")
+}
+
+/// Value of a number as a float, `None` if it isn't a number or is too big to be a float
+pub fn to_float(term: Term) -> Option<f64> {
+    let f = match term.tag() {
+        Tag::Small => term.small_val() as f64,
+        Tag::Big => term.big_val().to_f64()?,
+        Tag::Float => term.float_val(),
+        _ => return None,
+    };
+    f.is_finite().then_some(f)
+}
+
+/// Applies `op` to two numbers. Fails with `badarith` if either one isn't a number, if the
+/// operation only works on integers and one is a float, on division by zero and if a float result
+/// isn't finite.
+pub fn eval(op: ArithOp, a: Term, b: Term) -> Result<DataObject, DataObject> {
+    if op == ArithOp::FDiv || a.is_float() || b.is_float() {
+        return float(op, a, b);
+    }
+    if a.is_small()
+        && b.is_small()
+        && let Some(res) = small(op, a.small_val(), b.small_val())?
+    {
+        return Ok(DataObject::from(res));
+    }
+    let (Some(a), Some(b)) = (a.int_val(), b.int_val()) else {
+        return Err(badarith());
+    };
+    big(op, a, b).map(DataObject::from)
+}
+
+/// Applies `op` to a number, failing with `badarith` if it isn't one or if the operation only
+/// works on integers and it is a float
+pub fn eval_unary(op: UnaryOp, a: Term) -> Result<DataObject, DataObject> {
+    if a.is_float() {
+        let a = a.float_val();
+        return match op {
+            UnaryOp::Neg => Ok(DataObject::Float(-a)),
+            UnaryOp::Abs => Ok(DataObject::Float(a.abs())),
+            UnaryOp::Bnot => Err(badarith()),
+        };
+    }
+    if a.is_small() {
+        // Smalls are narrower than an `i64`, so none of these overflow
+        let a = a.small_val();
+        return Ok(DataObject::from(match op {
+            UnaryOp::Neg => -a,
+            UnaryOp::Abs => a.abs(),
+            UnaryOp::Bnot => !a,
+        }));
+    }
+    let a = a.int_val().ok_or_else(badarith)?;
+    Ok(DataObject::from(match op {
+        UnaryOp::Neg => -a,
+        UnaryOp::Abs => a.abs(),
+        UnaryOp::Bnot => !a,
+    }))
+}
+
+fn float(op: ArithOp, a: Term, b: Term) -> Result<DataObject, DataObject> {
+    let (Some(a), Some(b)) = (to_float(a), to_float(b)) else {
+        return Err(badarith());
+    };
+    let res = match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::FDiv => a / b,
+        _ => return Err(badarith()),
+    };
+    if res.is_finite() {
+        Ok(DataObject::Float(res))
+    } else {
+        Err(badarith())
+    }
+}
+
+/// Fast path for smalls, `None` if the result doesn't fit in an `i64`
+fn small(op: ArithOp, a: i64, b: i64) -> Result<Option<i64>, DataObject> {
+    if matches!(op, ArithOp::Div | ArithOp::Rem) && b == 0 {
+        return Err(badarith());
+    }
+    Ok(match op {
+        ArithOp::Add => a.checked_add(b),
+        ArithOp::Sub => a.checked_sub(b),
+        ArithOp::Mul => a.checked_mul(b),
+        ArithOp::Div => a.checked_div(b),
+        ArithOp::Rem => a.checked_rem(b),
+        ArithOp::Band => Some(a & b),
+        ArithOp::Bor => Some(a | b),
+        ArithOp::Bxor => Some(a ^ b),
+        ArithOp::Bsl | ArithOp::Bsr => {
+            let left = if op == ArithOp::Bsl { b } else { -b };
+            match left {
+                // Checks that no bits were shifted out
+                0..64 => a.checked_shl(left as u32).filter(|res| res >> left == a),
+                i64::MIN..0 => Some(a >> (-left).min(63)),
+                _ => None,
+            }
+        }
+        ArithOp::FDiv => unreachable!("always done on floats"),
+    })
+}
+
+fn big(op: ArithOp, a: BigInt, b: BigInt) -> Result<BigInt, DataObject> {
+    if matches!(op, ArithOp::Div | ArithOp::Rem) && b.is_zero() {
+        return Err(badarith());
+    }
+    Ok(match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::Div => a / b,
+        ArithOp::Rem => a % b,
+        ArithOp::Band => a & b,
+        ArithOp::Bor => a | b,
+        ArithOp::Bxor => a ^ b,
+        ArithOp::Bsl | ArithOp::Bsr => {
+            // Shifting left by a negative amount shifts right and the other way around
+            let left = if op == ArithOp::Bsl { b } else { -b };
+            if !left.is_negative() {
+                match left.to_u64() {
+                    _ if a.is_zero() => a,
+                    Some(left) if a.bits() + left <= MAX_BIG_DIGITS * 64 => a << left,
+                    _ => return Err(DataObject::atom("system_limit")),
+                }
+            } else {
+                match (-left).to_u64() {
+                    Some(right) if right < a.bits() => a >> right,
+                    // Everything is shifted out, leaving only the sign
+                    _ if a.is_negative() => BigInt::from(-1),
+                    _ => BigInt::zero(),
+                }
+            }
+        }
+        ArithOp::FDiv => unreachable!("always done on floats"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use crate::mem::{
+        DataObject,
+        heap::Heap,
+        term::{MAX_SMALL, MIN_SMALL, Term},
+    };
+
+    use super::{ArithOp, UnaryOp, eval, eval_unary};
+
+    fn int(n: impl Into<BigInt>) -> DataObject {
+        DataObject::from(n.into())
+    }
+
+    #[test]
+    fn promotion() {
+        let mut heap = Heap::new(64);
+        let mut eval = |op, a: DataObject, b: DataObject| {
+            let (a, b) = (heap.build(&a), heap.build(&b));
+            eval(op, a, b)
+        };
+        let max = BigInt::from(MAX_SMALL);
+        let min = BigInt::from(MIN_SMALL);
+
+        assert_eq!(eval(ArithOp::Add, int(1), int(2)), Ok(int(3)));
+        assert_eq!(
+            eval(ArithOp::Add, int(MAX_SMALL), int(1)),
+            Ok(int(&max + 1))
+        );
+        assert!(matches!(
+            eval(ArithOp::Add, int(MAX_SMALL), int(1)),
+            Ok(DataObject::Big(_))
+        ));
+        assert_eq!(
+            eval(ArithOp::Sub, int(MIN_SMALL), int(1)),
+            Ok(int(&min - 1))
+        );
+        assert_eq!(
+            eval(ArithOp::Mul, int(MAX_SMALL), int(MAX_SMALL)),
+            Ok(int(&max * &max))
+        );
+        // Results that fit are demoted back to smalls
+        assert_eq!(
+            eval(ArithOp::Sub, int(&max + 1), int(1)),
+            Ok(DataObject::Small(MAX_SMALL))
+        );
+        assert_eq!(
+            eval(ArithOp::Div, int(&max * &max), int(MAX_SMALL)),
+            Ok(DataObject::Small(MAX_SMALL))
+        );
+        assert_eq!(eval(ArithOp::Div, int(MIN_SMALL), int(-1)), Ok(int(-&min)));
+
+        assert_eq!(eval(ArithOp::Div, int(-7), int(2)), Ok(int(-3)));
+        assert_eq!(eval(ArithOp::Rem, int(-7), int(2)), Ok(int(-1)));
+        assert_eq!(eval(ArithOp::Rem, int(&max * 10 + 3), int(10)), Ok(int(3)));
+        assert_eq!(
+            eval(ArithOp::Band, int(0b1100), int(0b1010)),
+            Ok(int(0b1000))
+        );
+        assert_eq!(
+            eval(ArithOp::Bor, int(0b1100), int(0b1010)),
+            Ok(int(0b1110))
+        );
+        assert_eq!(
+            eval(ArithOp::Bxor, int(0b1100), int(0b1010)),
+            Ok(int(0b0110))
+        );
+        assert_eq!(
+            eval(ArithOp::Band, int(-1), int(&max * 4)),
+            Ok(int(&max * 4))
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        let mut heap = Heap::new(64);
+        let mut eval = |op, a: DataObject, b: DataObject| {
+            let (a, b) = (heap.build(&a), heap.build(&b));
+            eval(op, a, b)
+        };
+        let big = BigInt::from(1) << 100u32;
+
+        assert_eq!(eval(ArithOp::Bsl, int(1), int(4)), Ok(int(16)));
+        assert_eq!(eval(ArithOp::Bsl, int(1), int(100)), Ok(int(big.clone())));
+        assert_eq!(eval(ArithOp::Bsl, int(-1), int(100)), Ok(int(-&big)));
+        assert_eq!(eval(ArithOp::Bsl, int(16), int(-4)), Ok(int(1)));
+        assert_eq!(eval(ArithOp::Bsr, int(16), int(4)), Ok(int(1)));
+        assert_eq!(eval(ArithOp::Bsr, int(-16), int(100)), Ok(int(-1)));
+        assert_eq!(eval(ArithOp::Bsr, int(big.clone()), int(99)), Ok(int(2)));
+        assert_eq!(eval(ArithOp::Bsr, int(big.clone()), int(1000)), Ok(int(0)));
+        assert_eq!(eval(ArithOp::Bsr, int(-&big), int(1000)), Ok(int(-1)));
+        assert_eq!(eval(ArithOp::Bsr, int(1), int(-100)), Ok(int(big.clone())));
+        assert_eq!(eval(ArithOp::Bsl, int(0), int(big.clone())), Ok(int(0)));
+        assert_eq!(
+            eval(ArithOp::Bsl, int(1), int(big.clone())),
+            Err(DataObject::atom("system_limit"))
+        );
+    }
+
+    #[test]
+    fn unary() {
+        let mut heap = Heap::new(16);
+        let mut eval = |op, a: DataObject| {
+            let a = heap.build(&a);
+            eval_unary(op, a)
+        };
+        let min = BigInt::from(MIN_SMALL);
+
+        assert_eq!(eval(UnaryOp::Neg, int(5)), Ok(int(-5)));
+        assert_eq!(eval(UnaryOp::Neg, int(MIN_SMALL)), Ok(int(-&min)));
+        assert!(matches!(
+            eval(UnaryOp::Neg, int(MIN_SMALL)),
+            Ok(DataObject::Big(_))
+        ));
+        assert_eq!(
+            eval(UnaryOp::Neg, int(-&min)),
+            Ok(DataObject::Small(MIN_SMALL))
+        );
+        assert_eq!(eval(UnaryOp::Abs, int(-5)), Ok(int(5)));
+        assert_eq!(eval(UnaryOp::Abs, int(&min * 3)), Ok(int(-&min * 3)));
+        assert_eq!(eval(UnaryOp::Bnot, int(0)), Ok(int(-1)));
+        assert_eq!(eval(UnaryOp::Bnot, int(&min - 1)), Ok(int(-&min)));
+        assert_eq!(
+            eval(UnaryOp::Neg, DataObject::Float(1.5)),
+            Ok(DataObject::Float(-1.5))
+        );
+        assert_eq!(
+            eval(UnaryOp::Abs, DataObject::Float(-1.5)),
+            Ok(DataObject::Float(1.5))
+        );
+        assert_eq!(
+            eval(UnaryOp::Bnot, DataObject::Float(1.0)),
+            Err(DataObject::atom("badarith"))
+        );
+        assert_eq!(
+            eval(UnaryOp::Abs, DataObject::Nil),
+            Err(DataObject::atom("badarith"))
+        );
+    }
+
+    #[test]
+    fn floats() {
+        let mut heap = Heap::new(128);
+        let mut eval = |op, a: DataObject, b: DataObject| {
+            let (a, b) = (heap.build(&a), heap.build(&b));
+            eval(op, a, b)
+        };
+        let float = DataObject::Float;
+
+        assert_eq!(eval(ArithOp::Add, float(1.5), int(1)), Ok(float(2.5)));
+        assert_eq!(eval(ArithOp::Mul, int(3), float(0.5)), Ok(float(1.5)));
+        assert_eq!(eval(ArithOp::FDiv, int(3), int(2)), Ok(float(1.5)));
+        assert_eq!(eval(ArithOp::FDiv, int(4), int(2)), Ok(float(2.0)));
+        assert_eq!(
+            eval(ArithOp::Sub, int(BigInt::from(1) << 70u32), float(0.0)),
+            Ok(float(2f64.powi(70)))
+        );
+        let badarith = Err(DataObject::atom("badarith"));
+        assert_eq!(eval(ArithOp::FDiv, float(1.0), int(0)), badarith);
+        assert_eq!(eval(ArithOp::Mul, float(1e300), float(1e300)), badarith);
+        assert_eq!(eval(ArithOp::Div, float(4.0), int(2)), badarith);
+        assert_eq!(eval(ArithOp::Band, int(1), float(1.0)), badarith);
+        assert_eq!(eval(ArithOp::Add, float(1.0), DataObject::Nil), badarith);
+        assert_eq!(
+            eval(ArithOp::Add, int(BigInt::from(1) << 2000u32), float(1.0)),
+            badarith
+        );
+    }
+
+    #[test]
+    fn badarith() {
+        let badarith = Err(DataObject::atom("badarith"));
+        let mut heap = Heap::new(8);
+        let big = heap.build(&int(BigInt::from(MAX_SMALL) + 1));
+        assert_eq!(eval(ArithOp::Div, Term::small(1), Term::small(0)), badarith);
+        assert_eq!(eval(ArithOp::Rem, big, Term::small(0)), badarith);
+        assert_eq!(
+            eval(ArithOp::Add, Term::intern("a"), Term::small(1)),
+            badarith
+        );
+        assert_eq!(eval(ArithOp::Mul, big, Term::NIL), badarith);
+    }
+}
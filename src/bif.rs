@@ -0,0 +1,87 @@
+use crate::arith::{ArithOp, UnaryOp};
+
+/// Built in functions, called with the `bif` instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bif {
+    /// Arithmetic on numbers, like `erlang:'+'/2`
+    Arith(ArithOp),
+    /// Arithmetic on one number, like `erlang:abs/1`
+    Unary(UnaryOp),
+    GarbageCollect,
+    Element,
+    TupleSize,
+    ProcessFlag,
+    Link,
+    Unlink,
+    Exit,
+    Monitor,
+    Demonitor,
+    DemonitorOpts,
+    MakeRef,
+    Register,
+    Unregister,
+    Whereis,
+    Spawn,
+    SpawnLink,
+    SpawnFun,
+    SpawnLinkFun,
+    AtomToList,
+    ListToAtom,
+    ListToExistingAtom,
+    BinaryToAtom,
+    ByteSize,
+    BitSize,
+    /// Sub binary sharing the bytes of the original, like `erlang:binary_part/3`
+    BinaryPart,
+    BinaryToList,
+    ListToBinary,
+}
+
+impl Bif {
+    pub fn from_name(name: &str, arity: usize) -> Option<Self> {
+        match (name, arity) {
+            ("+", 2) => Some(Bif::Arith(ArithOp::Add)),
+            ("-", 2) => Some(Bif::Arith(ArithOp::Sub)),
+            ("*", 2) => Some(Bif::Arith(ArithOp::Mul)),
+            ("div", 2) => Some(Bif::Arith(ArithOp::Div)),
+            ("rem", 2) => Some(Bif::Arith(ArithOp::Rem)),
+            ("band", 2) => Some(Bif::Arith(ArithOp::Band)),
+            ("bor", 2) => Some(Bif::Arith(ArithOp::Bor)),
+            ("bxor", 2) => Some(Bif::Arith(ArithOp::Bxor)),
+            ("bsl", 2) => Some(Bif::Arith(ArithOp::Bsl)),
+            ("bsr", 2) => Some(Bif::Arith(ArithOp::Bsr)),
+            ("/", 2) => Some(Bif::Arith(ArithOp::FDiv)),
+            ("-", 1) => Some(Bif::Unary(UnaryOp::Neg)),
+            ("abs", 1) => Some(Bif::Unary(UnaryOp::Abs)),
+            ("bnot", 1) => Some(Bif::Unary(UnaryOp::Bnot)),
+            ("garbage_collect", 0) => Some(Bif::GarbageCollect),
+            ("element", 2) => Some(Bif::Element),
+            ("tuple_size", 1) => Some(Bif::TupleSize),
+            ("process_flag", 2) => Some(Bif::ProcessFlag),
+            ("link", 1) => Some(Bif::Link),
+            ("unlink", 1) => Some(Bif::Unlink),
+            ("exit", 2) => Some(Bif::Exit),
+            ("monitor", 2) => Some(Bif::Monitor),
+            ("demonitor", 1) => Some(Bif::Demonitor),
+            ("demonitor", 2) => Some(Bif::DemonitorOpts),
+            ("make_ref", 0) => Some(Bif::MakeRef),
+            ("register", 2) => Some(Bif::Register),
+            ("unregister", 1) => Some(Bif::Unregister),
+            ("whereis", 1) => Some(Bif::Whereis),
+            ("spawn", 3) => Some(Bif::Spawn),
+            ("spawn_link", 3) => Some(Bif::SpawnLink),
+            ("spawn", 1) => Some(Bif::SpawnFun),
+            ("spawn_link", 1) => Some(Bif::SpawnLinkFun),
+            ("atom_to_list", 1) => Some(Bif::AtomToList),
+            ("list_to_atom", 1) => Some(Bif::ListToAtom),
+            ("list_to_existing_atom", 1) => Some(Bif::ListToExistingAtom),
+            ("binary_to_atom", 2) => Some(Bif::BinaryToAtom),
+            ("byte_size", 1) => Some(Bif::ByteSize),
+            ("bit_size", 1) => Some(Bif::BitSize),
+            ("binary_part", 3) => Some(Bif::BinaryPart),
+            ("binary_to_list", 1) => Some(Bif::BinaryToList),
+            ("list_to_binary", 1) => Some(Bif::ListToBinary),
+            _ => None,
+        }
+    }
+}
@@ -30,29 +30,83 @@ Item -> Result<Item, ()>:
           let v = $1.map_err(|_| ())?;
           Ok(Item::Num(parse_int($lexer.span_str(v.span()))?))
       }
+    | 'FLOAT'
+      {
+          let v = $1.map_err(|_| ())?;
+          // Terms never hold NaN or infinity, so literals that overflow are rejected
+          let f: f64 = $lexer.span_str(v.span()).parse().map_err(|_| ())?;
+          if !f.is_finite() {
+              return Err(());
+          }
+          Ok(Item::Float(f))
+      }
     | 'ATOM'
       {
           let v = $1.map_err(|_| ())?;
           Ok(Item::Atom($lexer.span_str(v.span()).to_string()))
       }
+    | 'QUOTED_ATOM'
+      {
+          let v = $1.map_err(|_| ())?;
+          let quoted = $lexer.span_str(v.span());
+          Ok(Item::Atom(quoted[1..quoted.len() - 1].to_string()))
+      }
     | List { Ok(Item::List($1?)) }
+    | '[' ']' { Ok(Item::Bracket(Vec::new(), None)) }
+    | '[' Items ']' { Ok(Item::Bracket($2?, None)) }
+    | '[' Items '|' Item ']' { Ok(Item::Bracket($2?, Some(Box::new($4?)))) }
+    | '<<' '>>' { Ok(Item::Binary(Vec::new())) }
+    | '<<' Segments '>>' { Ok(Item::Binary($2?)) }
+    ;
+
+Segments -> Result<Vec<u8>, ()>:
+      Segments ',' Segment
+      {
+          let mut bytes = $1?;
+          bytes.extend($3?);
+          Ok(bytes)
+      }
+    | Segment { $1 }
+    ;
+
+Segment -> Result<Vec<u8>, ()>:
+      'INT'
+      {
+          let v = $1.map_err(|_| ())?;
+          let s = $lexer.span_str(v.span());
+          Ok(vec![s.parse().map_err(|_| eprintln!("{s} is not a byte"))?])
+      }
+    | 'STRING'
+      {
+          let v = $1.map_err(|_| ())?;
+          let quoted = $lexer.span_str(v.span());
+          Ok(quoted.as_bytes()[1..quoted.len() - 1].to_vec())
+      }
     ;
 %%
 // Any functions here are in scope for all the grammar actions above.
 
+use num_bigint::BigInt;
+
 pub type Prog = Vec<List>;
 pub type List = Vec<Item>;
 #[derive(Debug)]
 pub enum Item {
-    Num(u32),
+    /// Integer of any size, it becomes a bignum if it doesn't fit in a small
+    Num(BigInt),
+    Float(f64),
     Atom(String),
     List(List),
+    /// Erlang list literal, with an optional tail after a `|`
+    Bracket(List, Option<Box<Item>>),
+    /// Binary literal like `<<1, 2, 3>>` or `<<"text">>`, segments are bytes or strings
+    Binary(Vec<u8>),
 }
 
 impl Item {
     pub fn expect_num(&self) -> usize {
         if let Item::Num(x) = self {
-            (*x).try_into().unwrap()
+            x.try_into().unwrap()
         } else {
             panic!("expected num, got {self:?}");
         }
@@ -73,12 +127,6 @@ impl Item {
     }
 }
 
-fn parse_int(s: &str) -> Result<u32, ()> {
-    match s.parse::<u32>() {
-        Ok(val) => Ok(val),
-        Err(_) => {
-            eprintln!("{s} cannot be represented as a u64");
-            Err(())
-        }
-    }
+fn parse_int(s: &str) -> Result<BigInt, ()> {
+    s.parse().map_err(|_| eprintln!("{s} is not an integer"))
 }
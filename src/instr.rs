@@ -1,27 +1,260 @@
-use crate::{Reg, mem::DataObject};
+use crate::{Reg, bif::Bif, mem::DataObject, module::MFA};
 
-// TODO: wish we didn't have to clone the dataobject
 #[derive(Debug, Clone)]
 pub enum Instruction {
-    Move { dest: Reg, src: DataObject },
-    Add { arg0: Reg, arg1: Reg, ret: Reg },
-    Allocate { stack_need: usize },
+    Move {
+        dest: Reg,
+        src: DataObject,
+    },
+    Add {
+        arg0: Reg,
+        arg1: Reg,
+        ret: Reg,
+    },
+    Allocate {
+        stack_need: usize,
+    },
+    TestHeap {
+        need: usize,
+    },
+    Bif {
+        bif: Bif,
+        args: Vec<Reg>,
+        ret: Reg,
+    },
+    /// BIF that may need heap space, like arithmetic. Jumps to `fail` if the BIF fails, and
+    /// raises the error if there is no fail label.
+    GcBif {
+        bif: Bif,
+        fail: Option<usize>,
+        args: Vec<Src>,
+        ret: Reg,
+    },
+
+    PutList {
+        hd: Src,
+        tl: Src,
+        dest: Reg,
+    },
+    GetList {
+        src: Reg,
+        hd: Reg,
+        tl: Reg,
+    },
+    GetHd {
+        src: Reg,
+        dest: Reg,
+    },
+    GetTl {
+        src: Reg,
+        dest: Reg,
+    },
+
+    PutTuple2 {
+        dest: Reg,
+        elems: Vec<Src>,
+    },
+    /// Zero-indexed
+    GetTupleElement {
+        src: Reg,
+        index: usize,
+        dest: Reg,
+    },
+    /// Destructively updates a tuple, only meant for tuples that were just built
+    SetTupleElement {
+        elem: Src,
+        tuple: Reg,
+        index: usize,
+    },
 
     // TODO: actual labels and not offsets
-    IsLt { lbl: usize, arg0: Reg, arg1: Reg },
-    IsGe { lbl: usize, arg0: Reg, arg1: Reg },
-    IsEq { lbl: usize, arg0: Reg, arg1: Reg },
-    IsNe { lbl: usize, arg0: Reg, arg1: Reg },
+    IsLt {
+        lbl: usize,
+        arg0: Reg,
+        arg1: Reg,
+    },
+    IsGe {
+        lbl: usize,
+        arg0: Reg,
+        arg1: Reg,
+    },
+    IsEq {
+        lbl: usize,
+        arg0: Reg,
+        arg1: Reg,
+    },
+    IsNe {
+        lbl: usize,
+        arg0: Reg,
+        arg1: Reg,
+    },
 
-    IsInteger { lbl: usize, arg: Reg },
+    IsList {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsNonemptyList {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsNil {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsInteger {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsFloat {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsReference {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsBinary {
+        lbl: usize,
+        arg: Reg,
+    },
+    /// Every bitstring is a binary for now, since binaries can't be split within a byte
+    IsBitstring {
+        lbl: usize,
+        arg: Reg,
+    },
+    IsTuple {
+        lbl: usize,
+        arg: Reg,
+    },
+    TestArity {
+        lbl: usize,
+        arg: Reg,
+        arity: usize,
+    },
 
-    Jmp { lbl: usize },
+    Jmp {
+        lbl: usize,
+    },
     Ret,
-    Call { ip: usize },
+    Call {
+        ip: usize,
+    },
+    /// Calls a function in another module
+    CallExt {
+        func: ExtFunc,
+    },
+    /// Tail call to a function in another module
+    CallExtOnly {
+        func: ExtFunc,
+    },
+    /// Deallocates `dealloc` stack words and makes a tail call to a function in another module
+    CallExtLast {
+        func: ExtFunc,
+        dealloc: usize,
+    },
+
+    /// Moves a float from a term into a float register, or boxes the float in a float register
+    FMove {
+        src: Src,
+        dest: Reg,
+    },
+    /// Converts a number to a float, failing with `badarith` if it isn't one
+    FConv {
+        src: Src,
+        dest: usize,
+    },
+    // Float arithmetic on float registers. Infinite and NaN results aren't errors until the next
+    // `fcheckerror`.
+    FAdd {
+        arg0: usize,
+        arg1: usize,
+        dest: usize,
+    },
+    FSub {
+        arg0: usize,
+        arg1: usize,
+        dest: usize,
+    },
+    FMul {
+        arg0: usize,
+        arg1: usize,
+        dest: usize,
+    },
+    FDiv {
+        arg0: usize,
+        arg1: usize,
+        dest: usize,
+    },
+    FNegate {
+        arg: usize,
+        dest: usize,
+    },
+    FClearError,
+    /// Fails with `badarith` if a float instruction since the last `fclearerror` didn't give a
+    /// finite result
+    FCheckError,
 
-    // TODO: is this how we do this
-    Spawn { instrs: Vec<Instruction> },
+    /// Marks the start of a function, which is entered at the next instruction. Only reached when
+    /// no clause of the function matched.
+    FuncInfo {
+        module: String,
+        function: String,
+        arity: usize,
+    },
+    /// Puts a fun starting at `lbl` in X0, capturing the first `num_free` X registers
+    MakeFun {
+        lbl: usize,
+        arity: usize,
+        num_free: usize,
+    },
 
     Send,
-    Wait,
+    LoopRec {
+        lbl: usize,
+        dest: Reg,
+    },
+    LoopRecEnd {
+        lbl: usize,
+    },
+    RemoveMessage,
+    Wait {
+        lbl: usize,
+    },
+    /// `time` is in milliseconds or `infinity`
+    WaitTimeout {
+        lbl: usize,
+        time: Src,
+    },
+    Timeout,
+    RecvMark {
+        lbl: usize,
+    },
+    RecvSet {
+        lbl: usize,
+    },
+}
+
+/// Function called by the `call_ext` instructions. The loader replaces the name with an index
+/// into the import table of the module, see `Module`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtFunc {
+    Name(MFA),
+    Import(usize),
+}
+
+/// Operand that is either read from a register or a literal
+#[derive(Debug, Clone)]
+pub enum Src {
+    Reg(Reg),
+    Lit(DataObject),
+}
+
+impl Src {
+    /// Number of heap words needed to read this operand
+    pub fn size(&self) -> usize {
+        match self {
+            Src::Reg(_) => 0,
+            Src::Lit(data) => data.size(),
+        }
+    }
 }
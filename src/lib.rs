@@ -1,14 +1,20 @@
-#![feature(mapped_lock_guards)]
-
+pub use bif::Bif;
 pub use instr::Instruction;
-pub use mem::{DataObject, PID, stack::Reg};
-pub use parsing::{Item, List, Prog, parse_str};
+pub use mem::{DataObject, PID, atom::set_limit as set_atom_limit, stack::Reg, term::Term};
+pub use module::Module;
+pub use parsing::{Item, List, Prog, Source, parse_module, parse_str};
+pub use pcb::Reductions;
 pub use vm::VM;
 
+mod arith;
+mod bif;
 mod instr;
 mod mem;
 mod message;
+mod module;
 mod parsing;
 mod pcb;
+mod process_table;
+mod registry;
 mod scheduler;
 mod vm;
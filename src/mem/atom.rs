@@ -0,0 +1,166 @@
+//! Atom table. Atoms are interned when code is loaded or when a BIF like `list_to_atom/1` makes
+//! one, from then on they are only an index so comparing them is a word compare.
+//!
+//! There is one table for the whole OS process rather than one per VM, since atoms are plain
+//! indices that `DataObject`s carry from one VM to another. Every VM shares it, and its limit.
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Debug,
+    sync::{LazyLock, RwLock},
+};
+
+/// Most atoms BIFs can fill the table up to unless `set_limit` is called, same as ERTS
+pub const DEFAULT_LIMIT: usize = 1 << 20;
+
+/// Longest atom name in characters, same as ERTS
+pub const MAX_CHARS: usize = 255;
+
+/// Atoms are stored in terms as indices into this table
+#[derive(Debug)]
+struct AtomTable {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+    limit: usize,
+}
+
+impl AtomTable {
+    fn new(limit: usize) -> Self {
+        Self {
+            names: Vec::new(),
+            indices: HashMap::new(),
+            limit,
+        }
+    }
+
+    /// Adds `name` if it isn't there yet. Returns `None` if the table is full and the atom counts
+    /// towards the limit.
+    fn insert(&mut self, name: &str, limited: bool) -> Option<usize> {
+        if let Some(&index) = self.indices.get(name) {
+            return Some(index);
+        }
+        if limited && self.names.len() >= self.limit {
+            return None;
+        }
+        let index = self.names.len();
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), index);
+        Some(index)
+    }
+}
+
+static ATOMS: LazyLock<RwLock<AtomTable>> =
+    LazyLock::new(|| RwLock::new(AtomTable::new(DEFAULT_LIMIT)));
+
+/// Sets the most atoms BIFs can fill the table up to, for every VM in the OS process. Atoms that
+/// are already there are kept even if there are more than `limit`.
+pub fn set_limit(limit: usize) {
+    ATOMS.write().unwrap().limit = limit;
+}
+
+/// Returns the index of `name`, adding it to the table if it isn't there yet. Returns `None` if
+/// the atom is new but the table is full.
+pub fn try_intern(name: &str) -> Option<usize> {
+    if let Some(index) = existing(name) {
+        return Some(index);
+    }
+    // Someone may intern it between dropping the read lock and taking the write lock, which
+    // `insert` handles
+    ATOMS.write().unwrap().insert(name, true)
+}
+
+/// Like `try_intern`, for atoms in loaded code and the ones the runtime itself uses, like
+/// `badarg` or `noproc`. These don't count towards the limit, so that a table that BIFs filled up
+/// fails the process making atoms instead of the VM.
+pub fn intern(name: &str) -> usize {
+    if let Some(index) = existing(name) {
+        return index;
+    }
+    ATOMS.write().unwrap().insert(name, false).unwrap()
+}
+
+/// Index of `name` if it is already an atom
+pub fn existing(name: &str) -> Option<usize> {
+    ATOMS.read().unwrap().indices.get(name).copied()
+}
+
+pub fn name(index: usize) -> String {
+    ATOMS
+        .read()
+        .unwrap()
+        .names
+        .get(index)
+        .cloned()
+        .unwrap_or_else(|| panic!("atom {index} does not exist"))
+}
+
+/// Alphabetical order of two atoms
+pub fn compare(a: usize, b: usize) -> Ordering {
+    let table = ATOMS.read().unwrap();
+    table.names[a].cmp(&table.names[b])
+}
+
+/// Interned atom, see [`intern`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Atom(usize);
+
+impl Atom {
+    pub fn new(name: &str) -> Self {
+        Self(intern(name))
+    }
+
+    pub fn from_index(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    pub fn name(self) -> String {
+        name(self.0)
+    }
+}
+
+impl Debug for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{Atom, AtomTable, compare, existing, try_intern};
+
+    #[test]
+    fn interning() {
+        let a = Atom::new("atom_test_a");
+        assert_eq!(Atom::new("atom_test_a"), a);
+        assert_eq!(a.name(), "atom_test_a");
+        assert_eq!(try_intern("atom_test_a"), Some(a.index()));
+        assert_eq!(existing("atom_test_a"), Some(a.index()));
+        assert_eq!(existing("atom_test_never_interned"), None);
+
+        let b = Atom::new("atom_test_b");
+        assert_ne!(a, b);
+        assert_eq!(compare(a.index(), b.index()), Ordering::Less);
+        assert_eq!(compare(b.index(), a.index()), Ordering::Greater);
+    }
+
+    #[test]
+    fn limit() {
+        let mut table = AtomTable::new(2);
+        assert_eq!(table.insert("a", true), Some(0));
+        assert_eq!(table.insert("b", true), Some(1));
+        assert_eq!(table.insert("c", true), None);
+        // Atoms that are already there can still be looked up
+        assert_eq!(table.insert("a", true), Some(0));
+        // The runtime's own atoms go past the limit
+        assert_eq!(table.insert("c", false), Some(2));
+        assert_eq!(table.insert("d", true), None);
+        table.limit = 4;
+        assert_eq!(table.insert("d", true), Some(3));
+    }
+}
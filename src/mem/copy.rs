@@ -0,0 +1,154 @@
+//! Copying terms from one heap to another, which is how messages get from the sender to the
+//! receiver. Like in ERTS, subterms that are shared in the original are copied once per
+//! reference, while the bytes of refc binaries are shared rather than copied.
+use super::{heap::Heap, term::Term};
+
+/// Number of words `copy` needs for `term`
+pub fn size(term: Term) -> usize {
+    let mut size = 0;
+    let mut todo = vec![term];
+    while let Some(term) = todo.pop() {
+        if term.is_list() {
+            size += 2;
+            todo.push(term.hd());
+            todo.push(term.tl());
+        } else if term.is_boxed() {
+            let header = term.boxed_header();
+            size += header.header_arity() + 1;
+            if header.header_has_terms() {
+                // SAFETY: the object has `header_arity` words after its header
+                todo.extend((1..=header.header_arity()).map(|i| unsafe { *term.ptr().add(i) }));
+            }
+        }
+    }
+    size
+}
+
+/// Copies the object `term` points to, but not the objects it references in turn
+fn shallow_copy(term: Term, heap: &mut Heap) -> Term {
+    let (words, new) = if term.is_list() {
+        (2, Term::list(heap.alloc(2)))
+    } else if term.is_boxed() {
+        let words = term.boxed_header().header_arity() + 1;
+        (words, Term::boxed(heap.alloc(words)))
+    } else {
+        return term;
+    };
+    // SAFETY: `term` lives on another heap, so the freshly allocated words don't overlap it
+    unsafe { new.ptr().copy_from_nonoverlapping(term.ptr(), words) };
+    heap.share_off_heap(new);
+    new
+}
+
+/// Copies `term` onto `heap`, which must have `size(term)` words available. Works breadth first
+/// through the copied words like the garbage collector, so long lists don't use up the stack.
+pub fn copy(term: Term, heap: &mut Heap) -> Term {
+    let mut scan = heap.htop();
+    let new = shallow_copy(term, heap);
+    while scan < heap.htop() {
+        let word = heap.word(scan);
+        if word.is_header() {
+            if !word.header_has_terms() {
+                scan += word.header_arity();
+            }
+        } else {
+            let copied = shallow_copy(word, heap);
+            heap.set_word(scan, copied);
+        }
+        scan += 1;
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::mem::{DataObject, Ref, atom::Atom, heap::Heap, term::Term};
+
+    use super::{copy, size};
+
+    #[test]
+    fn copy_terms() {
+        let data = DataObject::Tuple(vec![
+            DataObject::atom("ok"),
+            DataObject::list(
+                vec![
+                    DataObject::Small(1),
+                    DataObject::Tuple(vec![DataObject::Nil]),
+                ],
+                DataObject::Small(2),
+            ),
+            DataObject::Refer(Ref::new()),
+            DataObject::Fun {
+                module: Atom::new("test"),
+                entry: 0,
+                arity: 0,
+                env: vec![DataObject::list(
+                    vec![DataObject::Small(3)],
+                    DataObject::Nil,
+                )],
+            },
+            DataObject::Binary(Arc::new(b"text".to_vec())),
+        ]);
+        let mut from = Heap::new(data.size());
+        let term = from.build(&data);
+        assert_eq!(size(term), data.size());
+
+        let mut to = Heap::new(size(term));
+        let copied = copy(term, &mut to);
+        assert_eq!(to.available(), 0);
+        assert!(to.contains(copied.ptr()));
+        assert_eq!(DataObject::from(copied), data);
+
+        assert_eq!(size(Term::small(1)), 0);
+        assert_eq!(copy(Term::NIL, &mut to), Term::NIL);
+    }
+
+    #[test]
+    fn shared_subterms() {
+        let mut from = Heap::new(5);
+        let tuple = from.tuple(&[Term::small(1)]);
+        let pair = from.tuple(&[tuple, tuple]);
+        // The shared tuple is copied twice
+        assert_eq!(size(pair), 3 + 2 * 2);
+        let mut to = Heap::new(size(pair));
+        let copied = copy(pair, &mut to);
+        assert_ne!(copied.element(1), copied.element(2));
+        assert_eq!(copied.element(1).element(1), Term::small(1));
+    }
+
+    #[test]
+    fn refc_binaries() {
+        let data = Arc::new(vec![7; 1000]);
+        let mut from = Heap::new(8);
+        let bin = from.refc_binary(data.clone());
+        let sub = from.sub_binary(bin, 10, 20);
+        assert_eq!(size(sub), 4 + 2);
+
+        // The copy shares the bytes with the original
+        let mut to = Heap::new(size(sub));
+        let copied = copy(sub, &mut to);
+        assert_eq!(Arc::strong_count(&data), 3);
+        drop(from);
+        assert_eq!(Arc::strong_count(&data), 2);
+        assert_eq!(
+            DataObject::from(copied),
+            DataObject::Binary(Arc::new(vec![7; 20]))
+        );
+        drop(to);
+        assert_eq!(Arc::strong_count(&data), 1);
+    }
+
+    #[test]
+    fn long_list() {
+        let data = DataObject::list(
+            (0..100_000).map(DataObject::Small).collect(),
+            DataObject::Nil,
+        );
+        let mut from = Heap::new(data.size());
+        let term = from.build(&data);
+        let mut to = Heap::new(size(term));
+        assert_eq!(DataObject::from(copy(term, &mut to)), data);
+    }
+}
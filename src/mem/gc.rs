@@ -0,0 +1,279 @@
+//! Generational Cheney style copying collector for process heaps.
+//!
+//! New terms are allocated on the young heap. A minor collection copies everything reachable on
+//! the young heap onto the old heap and empties the young heap. When the old heap cannot take the
+//! survivors, a major collection copies everything reachable from both heaps onto a fresh old
+//! heap. Copied objects are overwritten with a [`Term::moved`] forwarding marker so that shared
+//! subterms are only copied once.
+//!
+//! Heap fragments holding received messages are treated like part of the young heap, so live
+//! messages end up on the old heap and the fragments are freed.
+//!
+//! The data of refc binaries is released along with the heaps they were on, unless a copy of the
+//! binary survived the collection.
+use std::ops::Range;
+
+use super::{
+    heap::{Heap, MIN_HEAP_SIZE},
+    term::{HEADER_MOVED, Term},
+};
+
+struct Collector<'a> {
+    from: Vec<Range<*const Term>>,
+    to: &'a mut Heap,
+}
+
+impl Collector<'_> {
+    fn in_from_space(&self, ptr: *const Term) -> bool {
+        self.from.iter().any(|range| range.contains(&ptr))
+    }
+
+    /// Copies the object `term` points to, if it is in from space, and returns the new term
+    fn copy(&mut self, term: Term) -> Term {
+        if !(term.is_boxed() || term.is_list()) || !self.in_from_space(term.ptr()) {
+            return term;
+        }
+
+        let ptr = term.ptr();
+        // SAFETY: `ptr` points into from space which stays alive for the whole collection
+        let first = unsafe { *ptr };
+        if first.is_header() && first.header_subtag() == HEADER_MOVED {
+            let to = first.moved_val();
+            return if term.is_boxed() {
+                Term::boxed(to)
+            } else {
+                Term::list(to)
+            };
+        }
+
+        let (words, new) = if term.is_boxed() {
+            let words = first.header_arity() + 1;
+            let new = self.to.alloc(words);
+            (words, Term::boxed(new))
+        } else {
+            (2, Term::list(self.to.alloc(2)))
+        };
+        // SAFETY: from and to space never overlap
+        unsafe {
+            new.ptr().copy_from_nonoverlapping(ptr, words);
+            ptr.write(Term::moved(new.ptr()));
+        }
+        self.to.share_off_heap(new);
+        new
+    }
+
+    /// Copies everything referenced by the words in to space starting at `scan`
+    fn scan(&mut self, mut scan: usize) {
+        while scan < self.to.htop() {
+            let term = self.to.word(scan);
+            if term.is_header() {
+                // Tuple elements are terms and get scanned like any other word, most other boxed
+                // objects hold raw data that must be skipped
+                if !term.header_has_terms() {
+                    scan += term.header_arity();
+                }
+            } else {
+                let new = self.copy(term);
+                self.to.set_word(scan, new);
+            }
+            scan += 1;
+        }
+    }
+
+    fn collect(&mut self, roots: &mut [&mut [Term]]) {
+        let scan = self.to.htop();
+        for root in roots.iter_mut().flat_map(|roots| roots.iter_mut()) {
+            *root = self.copy(*root);
+        }
+        self.scan(scan);
+    }
+}
+
+/// Collects `young` and makes sure at least `need` words can be allocated on it afterwards.
+/// Returns whether a major collection was needed.
+pub fn collect(
+    young: &mut Heap,
+    old: &mut Heap,
+    fragments: &mut Vec<Heap>,
+    roots: &mut [&mut [Term]],
+    need: usize,
+    major: bool,
+) -> bool {
+    let fragment_words: usize = fragments.iter().map(Heap::htop).sum();
+    let mut from: Vec<_> = fragments.iter().map(Heap::range).collect();
+    from.push(young.range());
+
+    // Everything on the young heap and in fragments might survive, so the old heap needs room for
+    // all of it
+    let major = major || !old.test_heap(young.htop() + fragment_words);
+    if major {
+        from.push(old.range());
+        let size = (young.htop() + fragment_words + old.htop()) * 2;
+        let mut to = Heap::new(size.max(MIN_HEAP_SIZE));
+        Collector { from, to: &mut to }.collect(roots);
+        *old = to;
+    } else {
+        Collector { from, to: old }.collect(roots);
+    }
+    fragments.clear();
+
+    if young.size() < need {
+        *young = Heap::new(need.next_power_of_two());
+    } else {
+        young.reset();
+    }
+    major
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::mem::{heap::Heap, term::Term};
+
+    use super::collect;
+
+    #[test]
+    fn minor() {
+        let mut young = Heap::new(16);
+        let mut old = Heap::new(16);
+
+        let inner = young.tuple(&[Term::small(1), Term::small(2)]);
+        // garbage
+        young.cons(Term::small(0), Term::NIL);
+        let list = young.cons(inner, Term::NIL);
+        let list = young.cons(inner, list);
+        let mut regs = [list, Term::small(3)];
+        assert_eq!(young.htop(), 9);
+
+        assert!(!collect(
+            &mut young,
+            &mut old,
+            &mut Vec::new(),
+            &mut [&mut regs],
+            0,
+            false
+        ));
+        assert_eq!(young.htop(), 0);
+        // the shared tuple is only copied once
+        assert_eq!(old.htop(), 7);
+
+        let [list, small] = regs;
+        assert!(old.contains(list.ptr()));
+        assert_eq!(small, Term::small(3));
+        assert_eq!(list.hd(), list.tl().hd());
+        assert!(old.contains(list.hd().ptr()));
+        assert_eq!(list.hd().element(1), Term::small(1));
+        assert_eq!(list.hd().element(2), Term::small(2));
+        assert_eq!(list.tl().tl(), Term::NIL);
+    }
+
+    #[test]
+    fn major() {
+        let mut young = Heap::new(16);
+        let mut old = Heap::new(4);
+
+        let tuple = young.tuple(&[Term::small(1), Term::small(2), Term::small(3)]);
+        let mut regs = [tuple];
+        collect(
+            &mut young,
+            &mut old,
+            &mut Vec::new(),
+            &mut [&mut regs],
+            0,
+            false,
+        );
+        assert_eq!(old.htop(), 4);
+
+        // the old heap is full so the next collection must be a major one
+        let [tuple] = regs;
+        let list = young.cons(tuple, Term::NIL);
+        young.cons(Term::NIL, Term::NIL);
+        let mut regs = [list];
+        assert!(collect(
+            &mut young,
+            &mut old,
+            &mut Vec::new(),
+            &mut [&mut regs],
+            32,
+            false
+        ));
+        assert_eq!(old.htop(), 6);
+        assert_eq!(young.htop(), 0);
+        assert!(young.test_heap(32));
+
+        let [list] = regs;
+        assert!(old.contains(list.ptr()));
+        assert!(old.contains(list.hd().ptr()));
+        assert_eq!(list.hd().element(3), Term::small(3));
+    }
+
+    #[test]
+    fn refc_binaries() {
+        let mut young = Heap::new(16);
+        let mut old = Heap::new(16);
+
+        let live = Arc::new(vec![1; 100]);
+        let dead = Arc::new(vec![2; 100]);
+        let bin = young.refc_binary(live.clone());
+        young.refc_binary(dead.clone());
+        let mut regs = [bin];
+        collect(
+            &mut young,
+            &mut old,
+            &mut Vec::new(),
+            &mut [&mut regs],
+            0,
+            false,
+        );
+        assert_eq!(Arc::strong_count(&live), 2);
+        assert_eq!(Arc::strong_count(&dead), 1);
+        assert_eq!(old.off_heap_count(), 1);
+
+        // A major collection releases binaries that died on the old heap
+        let mut regs = [Term::NIL];
+        collect(
+            &mut young,
+            &mut old,
+            &mut Vec::new(),
+            &mut [&mut regs],
+            0,
+            true,
+        );
+        assert_eq!(Arc::strong_count(&live), 1);
+    }
+
+    #[test]
+    fn fragments() {
+        let mut young = Heap::new(16);
+        let mut old = Heap::new(16);
+
+        let mut fragment = Heap::new(5);
+        let msg = fragment.tuple(&[Term::small(1), Term::small(2)]);
+        let msg = fragment.cons(msg, Term::NIL);
+        // messages received but already removed from the mailbox are garbage
+        let mut garbage = Heap::new(2);
+        garbage.cons(Term::small(0), Term::NIL);
+        let mut fragments = vec![fragment, garbage];
+
+        let local = young.cons(Term::small(3), msg);
+        let mut regs = [local, msg];
+        collect(
+            &mut young,
+            &mut old,
+            &mut fragments,
+            &mut [&mut regs],
+            0,
+            false,
+        );
+        assert!(fragments.is_empty());
+        assert_eq!(old.htop(), 7);
+
+        let [local, msg] = regs;
+        assert!(old.contains(local.ptr()));
+        assert!(old.contains(msg.ptr()));
+        assert!(old.contains(msg.hd().ptr()));
+        assert_eq!(local.tl(), msg);
+        assert_eq!(msg.hd().element(2), Term::small(2));
+    }
+}
@@ -1,7 +1,347 @@
-#![allow(dead_code)]
+use std::{ops::Range, sync::Arc};
 
-use super::DataObject;
+use num_bigint::{BigInt, Sign};
 
-// TODO: this is bad; for now the heap is just a vec indexed by usizes
-pub type Heap = Vec<DataObject>;
-pub type Pointer = usize;
+use super::{
+    DataObject, FLOAT_WORDS, HEAP_BIN_LIMIT, REF_WORDS, REFC_BIN_WORDS, Ref, SUB_BIN_WORDS,
+    big_words, binary_words,
+    term::{
+        HEADER_BIG, HEADER_FLOAT, HEADER_FUN, HEADER_HEAP_BIN, HEADER_REFC_BIN, HEADER_REFER,
+        HEADER_SUB_BIN, Term,
+    },
+};
+
+/// Heap size of a newly spawned process in words, same as ERTS
+pub const MIN_HEAP_SIZE: usize = 233;
+
+/// Contiguous block of words that terms are bump allocated into. The block is never reallocated,
+/// so boxed and list terms can point straight into it.
+#[derive(Debug)]
+pub struct Heap {
+    space: Box<[Term]>,
+    htop: usize,
+    /// Data of the refc binaries on this heap. They are released along with the heap, so a
+    /// binary that is still reachable must be shared with the heap it gets copied to, see
+    /// `share_off_heap`.
+    off_heap: Vec<Arc<Vec<u8>>>,
+}
+
+impl Heap {
+    pub fn new(size: usize) -> Self {
+        Self {
+            space: vec![Term::NIL; size].into_boxed_slice(),
+            htop: 0,
+            off_heap: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.space.len()
+    }
+
+    /// Number of words allocated so far
+    pub fn htop(&self) -> usize {
+        self.htop
+    }
+
+    pub fn available(&self) -> usize {
+        self.size() - self.htop
+    }
+
+    /// Whether `need` words can be allocated without collecting
+    pub fn test_heap(&self, need: usize) -> bool {
+        self.available() >= need
+    }
+
+    /// Bump allocates `words` words. Callers are expected to have checked `test_heap` first.
+    pub fn alloc(&mut self, words: usize) -> *mut Term {
+        assert!(
+            self.test_heap(words),
+            "heap overflow: {words} words requested but only {} available",
+            self.available()
+        );
+        let ptr = self.space[self.htop..].as_mut_ptr();
+        self.htop += words;
+        ptr
+    }
+
+    pub fn tuple(&mut self, elements: &[Term]) -> Term {
+        let ptr = self.alloc(elements.len() + 1);
+        // SAFETY: `alloc` returned `elements.len() + 1` words that nothing else points to
+        unsafe {
+            ptr.write(Term::arityval(elements.len()));
+            ptr.add(1)
+                .copy_from_nonoverlapping(elements.as_ptr(), elements.len());
+        }
+        Term::boxed(ptr)
+    }
+
+    pub fn cons(&mut self, hd: Term, tl: Term) -> Term {
+        let ptr = self.alloc(2);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(hd);
+            ptr.add(1).write(tl);
+        }
+        Term::list(ptr)
+    }
+
+    pub fn reference(&mut self, reference: Ref) -> Term {
+        let ptr = self.alloc(1 + REF_WORDS);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_REFER, REF_WORDS));
+            for (i, word) in reference.words().into_iter().enumerate() {
+                ptr.add(1 + i).write(Term::from_raw(word));
+            }
+        }
+        Term::boxed(ptr)
+    }
+
+    /// Builds `n` as a bignum even if it would fit in a small, see `DataObject::from`
+    pub fn big(&mut self, n: &BigInt) -> Term {
+        let words = big_words(n);
+        let ptr = self.alloc(words);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_BIG, words - 1));
+            ptr.add(1)
+                .write(Term::from_raw((n.sign() == Sign::Minus) as usize));
+            for (i, digit) in n.iter_u64_digits().enumerate() {
+                ptr.add(2 + i).write(Term::from_raw(digit as usize));
+            }
+        }
+        Term::boxed(ptr)
+    }
+
+    pub fn float(&mut self, f: f64) -> Term {
+        let ptr = self.alloc(1 + FLOAT_WORDS);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_FLOAT, FLOAT_WORDS));
+            ptr.add(1).write(Term::from_raw(f.to_bits() as usize));
+        }
+        Term::boxed(ptr)
+    }
+
+    /// Builds a heap binary, see `refc_binary` for big ones
+    pub fn binary(&mut self, bytes: &[u8]) -> Term {
+        let words = binary_words(bytes.len());
+        let ptr = self.alloc(words);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_HEAP_BIN, words - 1));
+            ptr.add(1).write(Term::from_raw(bytes.len()));
+            (ptr.add(2) as *mut u8).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+        }
+        Term::boxed(ptr)
+    }
+
+    pub fn refc_binary(&mut self, data: Arc<Vec<u8>>) -> Term {
+        let ptr = self.alloc(1 + REFC_BIN_WORDS);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_REFC_BIN, REFC_BIN_WORDS));
+            ptr.add(1)
+                .write(Term::from_raw(Arc::as_ptr(&data) as usize));
+        }
+        self.off_heap.push(data);
+        Term::boxed(ptr)
+    }
+
+    /// Slice of `size` bytes of `bin` starting at `offset`, which shares the bytes of `bin`
+    pub fn sub_binary(&mut self, bin: Term, offset: usize, size: usize) -> Term {
+        // Slices of slices point straight to the original binary
+        let (offset, bin) = match bin.sub_binary() {
+            Some((orig_offset, _, orig)) => (orig_offset + offset, orig),
+            None => (offset, bin),
+        };
+        let ptr = self.alloc(1 + SUB_BIN_WORDS);
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_SUB_BIN, SUB_BIN_WORDS));
+            ptr.add(1).write(Term::small(offset as i64));
+            ptr.add(2).write(Term::small(size as i64));
+            ptr.add(3).write(bin);
+        }
+        Term::boxed(ptr)
+    }
+
+    /// Called on an object that was just copied onto this heap word for word. Refc binaries take
+    /// another reference to their data so that it outlives the heap they were copied from.
+    pub fn share_off_heap(&mut self, copied: Term) {
+        if copied.is_refc_binary() {
+            self.off_heap.push(copied.refc_data());
+        }
+    }
+
+    pub fn fun(&mut self, module: Term, entry: usize, arity: usize, env: &[Term]) -> Term {
+        let ptr = self.alloc(4 + env.len());
+        // SAFETY: see `tuple`
+        unsafe {
+            ptr.write(Term::header(HEADER_FUN, 3 + env.len()));
+            ptr.add(1).write(module);
+            ptr.add(2).write(Term::small(entry as i64));
+            ptr.add(3).write(Term::small(arity as i64));
+            ptr.add(4).copy_from_nonoverlapping(env.as_ptr(), env.len());
+        }
+        Term::boxed(ptr)
+    }
+
+    /// Builds `data` on the heap. Needs `data.size()` free words.
+    pub fn build(&mut self, data: &DataObject) -> Term {
+        match data {
+            DataObject::Tuple(elems) => {
+                let elems: Vec<_> = elems.iter().map(|elem| self.build(elem)).collect();
+                self.tuple(&elems)
+            }
+            DataObject::List(elems, tail) => {
+                let tail = self.build(tail);
+                elems.iter().rev().fold(tail, |tail, elem| {
+                    let hd = self.build(elem);
+                    self.cons(hd, tail)
+                })
+            }
+            DataObject::Big(n) => self.big(n),
+            DataObject::Float(f) => self.float(*f),
+            DataObject::Binary(data) if data.len() <= HEAP_BIN_LIMIT => self.binary(data),
+            DataObject::Binary(data) => self.refc_binary(data.clone()),
+            DataObject::Refer(reference) => self.reference(*reference),
+            DataObject::Fun {
+                module,
+                entry,
+                arity,
+                env,
+            } => {
+                let env: Vec<_> = env.iter().map(|var| self.build(var)).collect();
+                self.fun(Term::atom(module.index()), *entry, *arity, &env)
+            }
+            _ => Term::try_from(data).unwrap(),
+        }
+    }
+
+    pub fn word(&self, i: usize) -> Term {
+        self.space[i]
+    }
+
+    pub fn set_word(&mut self, i: usize, term: Term) {
+        self.space[i] = term;
+    }
+
+    /// Throws away everything on the heap, only safe once nothing points into it anymore
+    pub fn reset(&mut self) {
+        self.htop = 0;
+        self.off_heap.clear();
+    }
+
+    /// Number of off-heap binaries this heap holds a reference to
+    #[allow(dead_code, reason = "only the heap and GC tests check it")]
+    pub fn off_heap_count(&self) -> usize {
+        self.off_heap.len()
+    }
+
+    /// Address range of the allocated part of the heap
+    pub fn range(&self) -> Range<*const Term> {
+        let start = self.space.as_ptr();
+        // SAFETY: htop is at most the length of the space
+        start..unsafe { start.add(self.htop) }
+    }
+
+    pub fn contains(&self, ptr: *const Term) -> bool {
+        self.range().contains(&ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::mem::{
+        DataObject, Ref,
+        atom::Atom,
+        term::{Tag, Term},
+    };
+
+    use super::Heap;
+
+    #[test]
+    fn bump_allocation() {
+        let mut heap = Heap::new(8);
+        assert!(heap.test_heap(8));
+        let tuple = heap.tuple(&[Term::small(1), Term::NIL]);
+        assert_eq!(heap.htop(), 3);
+        assert_eq!(tuple.tag(), Tag::Tuple);
+        assert_eq!(tuple.tuple_arity(), 2);
+        assert_eq!(tuple.element(1), Term::small(1));
+        assert_eq!(tuple.element(2), Term::NIL);
+        assert!(heap.contains(tuple.ptr()));
+
+        let list = heap.cons(tuple, Term::NIL);
+        let list = heap.cons(Term::small(0), list);
+        assert_eq!(heap.htop(), 7);
+        assert_eq!(list.hd(), Term::small(0));
+        assert_eq!(list.tl().hd(), tuple);
+        assert_eq!(list.tl().tl(), Term::NIL);
+
+        assert!(heap.test_heap(1));
+        assert!(!heap.test_heap(2));
+    }
+
+    #[test]
+    fn build() {
+        let data = DataObject::Tuple(vec![
+            DataObject::atom("ok"),
+            DataObject::Tuple(vec![DataObject::Small(1), DataObject::Nil]),
+            DataObject::Tuple(Vec::new()),
+            DataObject::list(
+                vec![DataObject::Small(1), DataObject::Small(2)],
+                DataObject::Nil,
+            ),
+            DataObject::list(vec![DataObject::Nil], DataObject::Small(3)),
+            DataObject::Refer(Ref::new()),
+            DataObject::Fun {
+                module: Atom::new("test"),
+                entry: 3,
+                arity: 1,
+                env: vec![DataObject::Tuple(vec![DataObject::Small(1)])],
+            },
+            DataObject::Binary(Arc::new(vec![1, 2, 3])),
+            DataObject::Binary(Arc::new(vec![0; 100])),
+        ]);
+        let mut heap = Heap::new(data.size());
+        let term = heap.build(&data);
+        assert_eq!(heap.available(), 0);
+        assert_eq!(DataObject::from(term), data);
+    }
+
+    #[test]
+    fn binaries() {
+        let mut heap = Heap::new(16);
+        let small = heap.binary(b"hello");
+        assert_eq!(heap.htop(), 3);
+        let data = Arc::new(b"hello world".repeat(10));
+        let big = heap.refc_binary(data.clone());
+        assert_eq!(heap.htop(), 5);
+        assert_eq!(Arc::strong_count(&data), 2);
+        assert!(!small.is_refc_binary() && big.is_refc_binary());
+        small.with_bytes(|bytes| assert_eq!(bytes, b"hello"));
+        assert_eq!(big.byte_size(), 110);
+
+        // Slices of slices point to the original binary
+        let world = heap.sub_binary(big, 6, 99);
+        let orld = heap.sub_binary(world, 1, 4);
+        assert_eq!(orld.sub_binary(), Some((7, 4, big)));
+        orld.with_bytes(|bytes| assert_eq!(bytes, b"orld"));
+
+        heap.reset();
+        assert_eq!(heap.off_heap_count(), 0);
+        assert_eq!(Arc::strong_count(&data), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overflow() {
+        let mut heap = Heap::new(2);
+        heap.tuple(&[Term::NIL, Term::NIL]);
+    }
+}
@@ -1,29 +1,59 @@
 use std::{
+    cell::Cell,
+    cmp::Ordering,
     fmt::Debug,
-    sync::{Arc, Mutex},
+    hash::Hash,
+    sync::{
+        Arc,
+        atomic::{self, AtomicU64, AtomicUsize},
+    },
 };
 
+use atom::Atom;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use term::Term;
+
+pub mod atom;
+pub mod copy;
+pub mod gc;
 pub mod heap;
 pub mod stack;
+pub mod term;
 
-// TODO: tagged pointers https://rust-hosted-langs.github.io/book/chapter-interp-tagged-ptrs.html
+/// Owned representation of a term, used for literals in instructions and for moving terms around
+/// outside of a process. At runtime terms are tagged words, see [`Term`].
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataObject {
-    Small(u32),
-    Big,
-    Float,
-    Atom(String),
-    Refer,
+    /// Integer that fits in a small, see [`Term::small`]. Use `DataObject::from` to get a
+    /// `Small` or a `Big` depending on the value.
+    Small(i64),
+    Big(BigInt),
+    Float(f64),
+    Atom(Atom),
+    Refer(Ref),
+    /// Starts at instruction `entry` of `module` with the arguments followed by `env` in X
+    /// registers
+    Fun {
+        module: Atom,
+        entry: usize,
+        arity: usize,
+        env: Vec<DataObject>,
+    },
     Port,
     Pid(PID),
-    Tuple,
+    Tuple(Vec<DataObject>),
     Nil,
-    List,
-    Arityval,
+    /// Elements followed by the tail, which is `Nil` for proper lists. Always has at least one
+    /// element, the empty list is `Nil`.
+    List(Vec<DataObject>, Box<DataObject>),
+    Arityval(usize),
     Moved,
     Catch,
     Thing,
-    Binary,
+    /// Bytes of a binary. Big ones are built as reference counted binaries that share `Arc` with
+    /// every copy, see [`HEAP_BIN_LIMIT`].
+    Binary(Arc<Vec<u8>>),
     Blank,
     IC(usize),
 
@@ -34,7 +64,49 @@ pub enum DataObject {
 }
 
 impl DataObject {
-    pub fn expect_int(&self) -> u32 {
+    /// Number of words needed to build this object on a process heap
+    pub fn size(&self) -> usize {
+        match self {
+            DataObject::Tuple(elems) => {
+                1 + elems.len() + elems.iter().map(Self::size).sum::<usize>()
+            }
+            DataObject::List(elems, tail) => {
+                2 * elems.len() + elems.iter().map(Self::size).sum::<usize>() + tail.size()
+            }
+            DataObject::Big(n) => big_words(n),
+            DataObject::Float(_) => 1 + FLOAT_WORDS,
+            DataObject::Refer(_) => 1 + REF_WORDS,
+            DataObject::Binary(data) => binary_words(data.len()),
+            DataObject::Fun { env, .. } => {
+                4 + env.len() + env.iter().map(Self::size).sum::<usize>()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Atom called `name`, interning it if needed
+    pub fn atom(name: &str) -> Self {
+        DataObject::Atom(Atom::new(name))
+    }
+
+    /// Builds a list out of `elems` and `tail`, merging the tail into the elements if it is a list
+    /// itself
+    pub fn list(mut elems: Vec<DataObject>, tail: DataObject) -> Self {
+        let tail = match tail {
+            DataObject::List(rest, tail) => {
+                elems.extend(rest);
+                *tail
+            }
+            tail => tail,
+        };
+        if elems.is_empty() {
+            tail
+        } else {
+            DataObject::List(elems, Box::new(tail))
+        }
+    }
+
+    pub fn expect_int(&self) -> i64 {
         if let DataObject::Small(v) = self {
             *v
         } else {
@@ -51,10 +123,56 @@ impl DataObject {
     }
 }
 
+impl From<i64> for DataObject {
+    fn from(n: i64) -> Self {
+        if Term::fits_small(n) {
+            DataObject::Small(n)
+        } else {
+            DataObject::Big(n.into())
+        }
+    }
+}
+
+impl From<BigInt> for DataObject {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) if Term::fits_small(n) => DataObject::Small(n),
+            _ => DataObject::Big(n),
+        }
+    }
+}
+
+/// Words a bignum takes on the heap: the header, the sign and the 64 bit digits of the magnitude
+pub fn big_words(n: &BigInt) -> usize {
+    2 + n.iter_u64_digits().len()
+}
+
+/// Largest binary that is stored on the process heap, bigger ones live off heap and are
+/// reference counted so that sending them doesn't copy the bytes. Same as ERTS.
+pub const HEAP_BIN_LIMIT: usize = 64;
+
+/// Words following the header of a reference counted binary
+pub const REFC_BIN_WORDS: usize = 1;
+
+/// Words following the header of a sub binary
+pub const SUB_BIN_WORDS: usize = 3;
+
+/// Words a binary of `len` bytes takes on the heap, depending on whether it is a heap binary or a
+/// reference counted one
+pub fn binary_words(len: usize) -> usize {
+    if len <= HEAP_BIN_LIMIT {
+        2 + len.div_ceil(size_of::<usize>())
+    } else {
+        1 + REFC_BIN_WORDS
+    }
+}
+
 // TODO: this is probably bad
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct PID {
+    /// Scheduler the process was last seen on. Processes migrate between schedulers when they
+    /// get stolen, so this is only a hint and is not part of the identity of the PID.
     scheduler: usize,
     num: usize,
 }
@@ -63,6 +181,45 @@ impl PID {
     pub fn new(scheduler: usize, num: usize) -> Self {
         Self { scheduler, num }
     }
+
+    /// Process table slot and serial, see `ProcessTable`
+    pub fn number(&self) -> usize {
+        self.num
+    }
+
+    pub fn scheduler(&self) -> usize {
+        self.scheduler
+    }
+
+    pub fn set_scheduler(&mut self, scheduler: usize) {
+        self.scheduler = scheduler;
+    }
+}
+
+impl PartialEq for PID {
+    fn eq(&self, other: &Self) -> bool {
+        self.num == other.num
+    }
+}
+
+impl Eq for PID {}
+
+impl Hash for PID {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.num.hash(state);
+    }
+}
+
+impl PartialOrd for PID {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PID {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.num.cmp(&other.num)
+    }
 }
 
 impl Debug for PID {
@@ -71,4 +228,160 @@ impl Debug for PID {
     }
 }
 
-pub type Registers = Arc<Mutex<[DataObject; 1024]>>;
+/// Words following the header of a reference on the heap
+pub const REF_WORDS: usize = 3;
+
+/// Words following the header of a float on the heap
+pub const FLOAT_WORDS: usize = 1;
+
+thread_local! {
+    /// Creation of the VM and id of the scheduler running on this thread, if any, and its
+    /// reference counter
+    static REF_SCHEDULER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    static NEXT_REF: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Makes references created on this thread come from scheduler `id`'s counter in the VM with
+/// `creation`, see [`next_creation`]
+pub fn set_ref_scheduler(creation: usize, id: usize) {
+    REF_SCHEDULER.set(Some((creation, id)));
+}
+
+/// Number that tells apart the references of different VMs in the same OS process, whose
+/// schedulers count from the same ids
+pub fn next_creation() -> usize {
+    static NEXT_CREATION: AtomicUsize = AtomicUsize::new(1);
+    NEXT_CREATION.fetch_add(1, atomic::Ordering::Relaxed)
+}
+
+/// Unique reference. Like in ERTS, every scheduler has its own counter so that making a reference
+/// doesn't need any synchronization.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ref {
+    /// VM that made the reference, 0 for references made outside of schedulers
+    creation: usize,
+    /// One more than the id of the scheduler that made the reference, 0 for references made
+    /// outside of schedulers
+    scheduler: usize,
+    num: u64,
+}
+
+impl Ref {
+    /// Creates a reference that is different from every other one created by this VM
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        match REF_SCHEDULER.get() {
+            Some((creation, id)) => {
+                let num = NEXT_REF.get();
+                NEXT_REF.set(num + 1);
+                Self {
+                    creation,
+                    scheduler: id + 1,
+                    num,
+                }
+            }
+            None => {
+                static NEXT_GLOBAL_REF: AtomicU64 = AtomicU64::new(0);
+                Self {
+                    creation: 0,
+                    scheduler: 0,
+                    num: NEXT_GLOBAL_REF.fetch_add(1, atomic::Ordering::Relaxed),
+                }
+            }
+        }
+    }
+
+    pub fn from_words(words: [usize; REF_WORDS]) -> Self {
+        let [creation, scheduler, num] = words;
+        Self {
+            creation,
+            scheduler,
+            num: num as u64,
+        }
+    }
+
+    pub fn words(&self) -> [usize; REF_WORDS] {
+        [self.creation, self.scheduler, self.num as usize]
+    }
+}
+
+impl Debug for Ref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#Ref<{}.{}.{}.{}>",
+            self.creation,
+            self.scheduler,
+            self.num >> 32,
+            self.num & u32::MAX as u64
+        )
+    }
+}
+
+/// Register file that starts out empty and grows as registers are written, up to `N` of them, so
+/// that short lived processes only pay for the registers their code uses. Registers that were
+/// never written read as `fill`.
+#[derive(Debug)]
+pub struct RegisterFile<T, const N: usize> {
+    regs: Vec<T>,
+    fill: T,
+}
+
+impl<T: Copy, const N: usize> RegisterFile<T, N> {
+    pub fn new(fill: T) -> Self {
+        Self {
+            regs: Vec::new(),
+            fill,
+        }
+    }
+
+    /// `None` if register `i` doesn't exist
+    pub fn get(&self, i: usize) -> Option<T> {
+        (i < N).then(|| self.regs.get(i).copied().unwrap_or(self.fill))
+    }
+
+    /// Writes to registers that don't exist are ignored
+    pub fn set(&mut self, i: usize, value: T) {
+        if i < N {
+            self.grow(i + 1);
+            self.regs[i] = value;
+        }
+    }
+
+    /// The first `n` registers
+    pub fn first(&mut self, n: usize) -> &[T] {
+        self.grow(n);
+        &self.regs[..n]
+    }
+
+    /// Every register that was ever written, the rest hold `fill`
+    pub fn written_mut(&mut self) -> &mut [T] {
+        &mut self.regs
+    }
+
+    fn grow(&mut self, len: usize) {
+        if self.regs.len() < len {
+            self.regs.resize(len, self.fill);
+        }
+    }
+}
+
+pub const NUM_X_REGS: usize = 1024;
+
+/// X register file. Every process owns one so that its registers survive preemption along with
+/// the rest of its context.
+pub type Registers = RegisterFile<Term, NUM_X_REGS>;
+
+pub fn new_registers() -> Registers {
+    RegisterFile::new(Term::NIL)
+}
+
+pub const NUM_FLOAT_REGS: usize = 256;
+
+/// Float registers, which hold raw floats so that float arithmetic doesn't box intermediate
+/// results
+pub type FloatRegisters = RegisterFile<f64, NUM_FLOAT_REGS>;
+
+pub fn new_float_registers() -> FloatRegisters {
+    RegisterFile::new(0.0)
+}
@@ -1,20 +1,21 @@
-use std::iter;
+use std::{iter, sync::Arc};
 
-use crate::instr::Instruction;
+use crate::module::Module;
 
-use super::DataObject;
+use super::term::Term;
 
 #[derive(Debug)]
 pub struct Stack {
-    registers: Vec<DataObject>,
+    registers: Vec<Term>,
     call_frames: Vec<CallFrame>,
-    instrs: Vec<Instruction>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Reg {
     X(usize),
     Y(usize),
+    /// Float register, only used by the float instructions
+    FR(usize),
     Htop,
     E,
     I,
@@ -36,36 +37,38 @@ pub struct CallFrame {
 
     /// base pointer
     bp: usize,
+
+    /// Module to return to, `None` for calls within a module
+    code: Option<Arc<Module>>,
 }
 
 impl CallFrame {
     pub fn new(ip: usize, bp: usize) -> Self {
-        Self { ip, bp }
+        Self { ip, bp, code: None }
     }
 }
 
 impl Stack {
-    pub fn new_from(instrs: Vec<Instruction>) -> Self {
+    pub fn new() -> Self {
         Self {
-            registers: vec![DataObject::Nil; 256],
+            registers: vec![Term::NIL; 256],
             call_frames: vec![CallFrame::new(0, 0)],
-            instrs,
         }
     }
 
-    pub fn get(&self, reg: &Reg) -> Result<DataObject, String> {
+    pub fn get(&self, reg: &Reg) -> Result<Term, String> {
         match reg {
             Reg::Y(i) => self
                 .registers
                 .get(*i)
                 .ok_or("register out of bounds".to_string())
-                .cloned(),
-            Reg::CP => Ok(DataObject::IC(self.cur_frame().ip)),
+                .copied(),
+            Reg::CP => Ok(Term::cp(self.cur_frame().ip)),
             _ => Err(format!("cannot get {reg:?} from stack")),
         }
     }
 
-    pub fn put(&mut self, reg: &Reg, data: DataObject) {
+    pub fn put(&mut self, reg: &Reg, data: Term) {
         match reg {
             Reg::Y(i) => {
                 if *i >= self.registers.len() {
@@ -74,8 +77,8 @@ impl Stack {
                 self.registers[*i] = data;
             }
             Reg::CP => {
-                if let DataObject::IC(ip) = data {
-                    self.cur_frame_mut().ip = ip;
+                if data.is_cp() {
+                    self.cur_frame_mut().ip = data.cp_val();
                 }
             }
             _ => panic!("cannot set {reg:?} from stack"),
@@ -95,8 +98,7 @@ impl Stack {
     }
 
     pub fn allocate(&mut self, words: usize) {
-        self.registers
-            .extend(iter::repeat_n(DataObject::Nil, words));
+        self.registers.extend(iter::repeat_n(Term::NIL, words));
     }
 
     pub fn deallocate(&mut self, words: usize) {
@@ -114,6 +116,17 @@ impl Stack {
         self.allocate(256);
     }
 
+    /// Call to another module that returns to `ip` in `code`
+    pub fn allocate_call_ext(&mut self, ip: usize, code: Arc<Module>) {
+        self.allocate_call(ip);
+        self.cur_frame_mut().code = Some(code);
+    }
+
+    /// Module the current frame returns to if it was a call to another module
+    pub fn return_code(&mut self) -> Option<Arc<Module>> {
+        self.cur_frame_mut().code.take()
+    }
+
     pub fn ret(&mut self) -> bool {
         self.call_frames.pop();
         if self.call_frames.is_empty() {
@@ -124,8 +137,9 @@ impl Stack {
         }
     }
 
-    pub fn instrs(&self) -> &[Instruction] {
-        &self.instrs
+    /// Y registers, which are roots for the garbage collector
+    pub fn registers_mut(&mut self) -> &mut [Term] {
+        &mut self.registers
     }
 
     pub fn cp(&self) -> Option<usize> {
@@ -135,16 +149,16 @@ impl Stack {
 
 #[cfg(test)]
 mod tests {
-    use crate::mem::{DataObject, stack::Reg};
+    use crate::mem::{stack::Reg, term::Term};
 
     use super::Stack;
 
     #[test]
     fn registers() {
-        let mut stack = Stack::new_from(Vec::new());
+        let mut stack = Stack::new();
         stack.allocate(1);
-        assert_eq!(stack.get(&Reg::Y(0)), Ok(DataObject::Nil));
-        stack.put(&Reg::Y(0), DataObject::Small(0));
-        assert_eq!(stack.get(&Reg::Y(0)), Ok(DataObject::Small(0)));
+        assert_eq!(stack.get(&Reg::Y(0)), Ok(Term::NIL));
+        stack.put(&Reg::Y(0), Term::small(0));
+        assert_eq!(stack.get(&Reg::Y(0)), Ok(Term::small(0)));
     }
 }
@@ -0,0 +1,976 @@
+//! One word tagged term representation, following the tagging scheme described in
+//! https://rust-hosted-langs.github.io/book/chapter-interp-tagged-ptrs.html and the ERTS sources.
+//!
+//! The two lowest bits are the primary tag:
+//!
+//! ```text
+//! 00 header    first word of a boxed object on the heap
+//! 01 list      pointer to a cons cell
+//! 10 boxed     pointer to a header word
+//! 11 immediate secondary tag in bits 2-3 (and 4-5 for atoms, catches, code pointers and nil)
+//! ```
+use std::{cmp::Ordering, fmt::Debug, ptr, sync::Arc};
+
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::FromPrimitive;
+
+use super::{
+    DataObject, PID, Ref,
+    atom::{self, Atom},
+};
+
+const PRIMARY_MASK: usize = 0b11;
+const PRIMARY_HEADER: usize = 0b00;
+const PRIMARY_LIST: usize = 0b01;
+const PRIMARY_BOXED: usize = 0b10;
+const PRIMARY_IMMED1: usize = 0b11;
+
+const IMMED1_BITS: usize = 4;
+const IMMED1_MASK: usize = 0b1111;
+const IMMED1_PID: usize = 0b0011;
+const IMMED1_PORT: usize = 0b0111;
+const IMMED1_IMMED2: usize = 0b1011;
+const IMMED1_SMALL: usize = 0b1111;
+
+const IMMED2_BITS: usize = 6;
+const IMMED2_MASK: usize = 0b11_1111;
+const IMMED2_ATOM: usize = 0b00_1011;
+const IMMED2_CATCH: usize = 0b01_1011;
+const IMMED2_CP: usize = 0b10_1011;
+const IMMED2_NIL: usize = 0b11_1011;
+
+const HEADER_BITS: usize = 6;
+const HEADER_MASK: usize = 0b11_1111;
+pub const HEADER_ARITYVAL: usize = 0b00_0000;
+pub const HEADER_MOVED: usize = 0b00_0100;
+/// Sign word, 1 if negative, followed by the 64 bit digits of the magnitude, least significant
+/// first
+pub const HEADER_BIG: usize = 0b00_1000;
+/// Bits of an `f64`, which is always finite: float instructions and arithmetic raise `badarith`
+/// rather than box NaN or infinity
+pub const HEADER_FLOAT: usize = 0b00_1100;
+pub const HEADER_REFER: usize = 0b01_0000;
+/// Pointer to the `Vec` of an off-heap binary, which the heap holds an `Arc` for
+pub const HEADER_REFC_BIN: usize = 0b01_0100;
+pub const HEADER_THING: usize = 0b01_1000;
+/// Module, entry point, arity and free variables of a fun. All the words are terms, so like tuples they
+/// get scanned by the garbage collector.
+pub const HEADER_FUN: usize = 0b01_1100;
+/// Byte size followed by the bytes of a small binary
+pub const HEADER_HEAP_BIN: usize = 0b10_0000;
+/// Byte offset and size as smalls, followed by the heap or refc binary they slice
+pub const HEADER_SUB_BIN: usize = 0b10_0100;
+
+/// Bits available for the value of a small integer
+pub const SMALL_BITS: usize = usize::BITS as usize - IMMED1_BITS;
+pub const MAX_SMALL: i64 = (1 << (SMALL_BITS - 1)) - 1;
+pub const MIN_SMALL: i64 = -(1 << (SMALL_BITS - 1));
+
+const PID_SCHEDULER_BITS: usize = 16;
+
+/// Kind of a term, named after the `DataObject` variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Small,
+    Big,
+    Float,
+    Atom,
+    Refer,
+    Port,
+    Pid,
+    Tuple,
+    Nil,
+    List,
+    Arityval,
+    Moved,
+    Catch,
+    Thing,
+    Binary,
+    Fun,
+
+    // Code pointers are word aligned so, like in the original BEAM, the low nibble of their byte
+    // address is one of 0, 4, 8 or 12
+    CP0,
+    CP4,
+    CP8,
+    CP12,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Term(usize);
+
+impl Term {
+    pub const NIL: Term = Term(IMMED2_NIL);
+
+    pub fn raw(self) -> usize {
+        self.0
+    }
+
+    pub fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    fn primary(self) -> usize {
+        self.0 & PRIMARY_MASK
+    }
+
+    fn immed1(self) -> usize {
+        self.0 & IMMED1_MASK
+    }
+
+    fn immed2(self) -> usize {
+        self.0 & IMMED2_MASK
+    }
+
+    pub fn small(v: i64) -> Self {
+        assert!(Self::fits_small(v), "{v} does not fit in a small");
+        Self(((v as usize) << IMMED1_BITS) | IMMED1_SMALL)
+    }
+
+    pub fn fits_small(v: i64) -> bool {
+        (MIN_SMALL..=MAX_SMALL).contains(&v)
+    }
+
+    pub fn atom(index: usize) -> Self {
+        Self((index << IMMED2_BITS) | IMMED2_ATOM)
+    }
+
+    /// Atom called `name`
+    pub fn intern(name: &str) -> Self {
+        Self::atom(atom::intern(name))
+    }
+
+    pub fn pid(pid: &PID) -> Self {
+        assert!(pid.scheduler < 1 << PID_SCHEDULER_BITS);
+        Self((((pid.num << PID_SCHEDULER_BITS) | pid.scheduler) << IMMED1_BITS) | IMMED1_PID)
+    }
+
+    pub fn port(index: usize) -> Self {
+        Self((index << IMMED1_BITS) | IMMED1_PORT)
+    }
+
+    pub fn catch(index: usize) -> Self {
+        Self((index << IMMED2_BITS) | IMMED2_CATCH)
+    }
+
+    /// Continuation pointer to instruction `ip`
+    pub fn cp(ip: usize) -> Self {
+        Self((ip << IMMED2_BITS) | IMMED2_CP)
+    }
+
+    pub fn boxed(ptr: *const Term) -> Self {
+        debug_assert_eq!(ptr as usize & PRIMARY_MASK, 0);
+        Self(ptr as usize | PRIMARY_BOXED)
+    }
+
+    pub fn list(ptr: *const Term) -> Self {
+        debug_assert_eq!(ptr as usize & PRIMARY_MASK, 0);
+        Self(ptr as usize | PRIMARY_LIST)
+    }
+
+    /// Header word of a boxed object with `arity` words following it
+    pub fn header(subtag: usize, arity: usize) -> Self {
+        debug_assert_eq!(subtag & !HEADER_MASK, 0);
+        Self((arity << HEADER_BITS) | subtag)
+    }
+
+    pub fn arityval(arity: usize) -> Self {
+        Self::header(HEADER_ARITYVAL, arity)
+    }
+
+    /// Forwarding marker left behind by the garbage collector
+    pub fn moved(to: *const Term) -> Self {
+        Self(((to as usize) << HEADER_BITS) | HEADER_MOVED)
+    }
+
+    pub fn tag(self) -> Tag {
+        match self.primary() {
+            PRIMARY_HEADER => match self.header_subtag() {
+                HEADER_ARITYVAL => Tag::Arityval,
+                HEADER_MOVED => Tag::Moved,
+                _ => Tag::Thing,
+            },
+            PRIMARY_LIST => Tag::List,
+            // SAFETY: boxed terms are only ever created from pointers to live header words
+            PRIMARY_BOXED => match unsafe { *self.ptr() }.header_subtag() {
+                HEADER_ARITYVAL => Tag::Tuple,
+                HEADER_MOVED => Tag::Moved,
+                HEADER_BIG => Tag::Big,
+                HEADER_FLOAT => Tag::Float,
+                HEADER_REFER => Tag::Refer,
+                HEADER_REFC_BIN | HEADER_HEAP_BIN | HEADER_SUB_BIN => Tag::Binary,
+                HEADER_THING => Tag::Thing,
+                HEADER_FUN => Tag::Fun,
+                subtag => unreachable!("invalid header subtag {subtag:#b}"),
+            },
+            _ => match self.immed1() {
+                IMMED1_PID => Tag::Pid,
+                IMMED1_PORT => Tag::Port,
+                IMMED1_SMALL => Tag::Small,
+                IMMED1_IMMED2 => match self.immed2() {
+                    IMMED2_ATOM => Tag::Atom,
+                    IMMED2_CATCH => Tag::Catch,
+                    IMMED2_NIL => Tag::Nil,
+                    _ => match (self.cp_val() * 4) % 16 {
+                        0 => Tag::CP0,
+                        4 => Tag::CP4,
+                        8 => Tag::CP8,
+                        _ => Tag::CP12,
+                    },
+                },
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    pub fn is_immed(self) -> bool {
+        self.primary() == PRIMARY_IMMED1
+    }
+
+    pub fn is_small(self) -> bool {
+        self.immed1() == IMMED1_SMALL
+    }
+
+    pub fn is_atom(self) -> bool {
+        self.immed2() == IMMED2_ATOM
+    }
+
+    pub fn is_pid(self) -> bool {
+        self.immed1() == IMMED1_PID
+    }
+
+    pub fn is_nil(self) -> bool {
+        self == Self::NIL
+    }
+
+    pub fn is_cp(self) -> bool {
+        self.immed2() == IMMED2_CP
+    }
+
+    pub fn is_boxed(self) -> bool {
+        self.primary() == PRIMARY_BOXED
+    }
+
+    pub fn is_list(self) -> bool {
+        self.primary() == PRIMARY_LIST
+    }
+
+    pub fn is_header(self) -> bool {
+        self.primary() == PRIMARY_HEADER
+    }
+
+    pub fn small_val(self) -> i64 {
+        debug_assert!(self.is_small());
+        (self.0 as i64) >> IMMED1_BITS
+    }
+
+    pub fn atom_val(self) -> usize {
+        debug_assert!(self.is_atom());
+        self.0 >> IMMED2_BITS
+    }
+
+    pub fn pid_val(self) -> PID {
+        debug_assert!(self.is_pid());
+        let v = self.0 >> IMMED1_BITS;
+        PID::new(v & ((1 << PID_SCHEDULER_BITS) - 1), v >> PID_SCHEDULER_BITS)
+    }
+
+    pub fn port_val(self) -> usize {
+        self.0 >> IMMED1_BITS
+    }
+
+    pub fn catch_val(self) -> usize {
+        self.0 >> IMMED2_BITS
+    }
+
+    pub fn cp_val(self) -> usize {
+        self.0 >> IMMED2_BITS
+    }
+
+    /// Address of the object a boxed or list term points to
+    pub fn ptr(self) -> *mut Term {
+        debug_assert!(self.is_boxed() || self.is_list());
+        (self.0 & !PRIMARY_MASK) as *mut Term
+    }
+
+    pub fn header_subtag(self) -> usize {
+        debug_assert!(self.is_header());
+        self.0 & HEADER_MASK
+    }
+
+    pub fn header_arity(self) -> usize {
+        debug_assert!(self.is_header());
+        self.0 >> HEADER_BITS
+    }
+
+    /// Whether the words after this header are terms rather than raw data
+    pub fn header_has_terms(self) -> bool {
+        matches!(
+            self.header_subtag(),
+            HEADER_ARITYVAL | HEADER_FUN | HEADER_SUB_BIN
+        )
+    }
+
+    pub fn moved_val(self) -> *mut Term {
+        debug_assert_eq!(self.tag(), Tag::Moved);
+        (self.0 >> HEADER_BITS) as *mut Term
+    }
+
+    // SAFETY for the accessors below: boxed and list terms always point to live objects on a heap
+
+    /// Header word of the object a boxed term points to
+    pub fn boxed_header(self) -> Term {
+        debug_assert!(self.is_boxed());
+        unsafe { *self.ptr() }
+    }
+
+    pub fn tuple_arity(self) -> usize {
+        debug_assert_eq!(self.tag(), Tag::Tuple);
+        self.boxed_header().header_arity()
+    }
+
+    /// One-indexed like `erlang:element/2`
+    pub fn element(self, i: usize) -> Term {
+        debug_assert!((1..=self.tuple_arity()).contains(&i));
+        unsafe { *self.ptr().add(i) }
+    }
+
+    pub fn set_element(self, i: usize, value: Term) {
+        debug_assert!((1..=self.tuple_arity()).contains(&i));
+        unsafe { self.ptr().add(i).write(value) }
+    }
+
+    pub fn is_tuple(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Tuple
+    }
+
+    pub fn is_ref(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Refer
+    }
+
+    pub fn is_big(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Big
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.is_small() || self.is_big()
+    }
+
+    pub fn big_val(self) -> BigInt {
+        debug_assert!(self.is_big());
+        let words = self.boxed_header().header_arity();
+        let sign = if unsafe { *self.ptr().add(1) }.raw() == 1 {
+            Sign::Minus
+        } else {
+            Sign::Plus
+        };
+        let digits: Vec<u32> = (2..=words)
+            .flat_map(|i| {
+                let digit = unsafe { *self.ptr().add(i) }.raw() as u64;
+                [digit as u32, (digit >> 32) as u32]
+            })
+            .collect();
+        BigInt::from_biguint(sign, BigUint::new(digits))
+    }
+
+    pub fn is_float(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Float
+    }
+
+    pub fn is_number(self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    pub fn float_val(self) -> f64 {
+        debug_assert!(self.is_float());
+        f64::from_bits(unsafe { *self.ptr().add(1) }.raw() as u64)
+    }
+
+    /// Value of an integer, whether it is a small or a bignum
+    pub fn int_val(self) -> Option<BigInt> {
+        match self.tag() {
+            Tag::Small => Some(self.small_val().into()),
+            Tag::Big => Some(self.big_val()),
+            _ => None,
+        }
+    }
+
+    pub fn is_fun(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Fun
+    }
+
+    /// Atom naming the module the fun's code is in
+    pub fn fun_module(self) -> Term {
+        debug_assert!(self.is_fun());
+        unsafe { *self.ptr().add(1) }
+    }
+
+    /// Instruction the fun's code starts at
+    pub fn fun_entry(self) -> usize {
+        debug_assert!(self.is_fun());
+        unsafe { *self.ptr().add(2) }.small_val() as usize
+    }
+
+    pub fn fun_arity(self) -> usize {
+        debug_assert!(self.is_fun());
+        unsafe { *self.ptr().add(3) }.small_val() as usize
+    }
+
+    /// Free variables captured when the fun was made
+    pub fn fun_env(self) -> Vec<Term> {
+        debug_assert!(self.is_fun());
+        let words = self.boxed_header().header_arity();
+        (4..=words).map(|i| unsafe { *self.ptr().add(i) }).collect()
+    }
+
+    pub fn is_binary(self) -> bool {
+        self.is_boxed() && self.tag() == Tag::Binary
+    }
+
+    /// Calls `f` with the bytes of a binary, without copying them
+    pub fn with_bytes<R>(self, f: impl FnOnce(&[u8]) -> R) -> R {
+        // SAFETY: the heap the binary is on keeps the bytes alive, including the data of refc
+        // binaries
+        f(unsafe { &*self.bytes() })
+    }
+
+    fn bytes(self) -> *const [u8] {
+        debug_assert!(self.is_binary());
+        let ptr = self.ptr();
+        let word = |i| unsafe { *ptr.add(i) };
+        match self.boxed_header().header_subtag() {
+            HEADER_HEAP_BIN => {
+                ptr::slice_from_raw_parts(unsafe { ptr.add(2) } as *const u8, word(1).raw())
+            }
+            HEADER_REFC_BIN => unsafe { &**(word(1).raw() as *const Vec<u8>) },
+            _ => {
+                let (offset, size, orig) = self.sub_binary().unwrap();
+                let orig = unsafe { &*orig.bytes() };
+                &orig[offset..offset + size]
+            }
+        }
+    }
+
+    pub fn byte_size(self) -> usize {
+        self.with_bytes(<[u8]>::len)
+    }
+
+    pub fn is_refc_binary(self) -> bool {
+        self.is_boxed() && self.boxed_header().header_subtag() == HEADER_REFC_BIN
+    }
+
+    /// Takes another reference to the off-heap data of a refc binary
+    pub fn refc_data(self) -> Arc<Vec<u8>> {
+        debug_assert!(self.is_refc_binary());
+        let data = unsafe { *self.ptr().add(1) }.raw() as *const Vec<u8>;
+        // SAFETY: the pointer came from an `Arc` that the heap the binary is on still holds
+        unsafe {
+            Arc::increment_strong_count(data);
+            Arc::from_raw(data)
+        }
+    }
+
+    /// Offset and size of a sub binary, and the binary it slices
+    pub fn sub_binary(self) -> Option<(usize, usize, Term)> {
+        if !self.is_boxed() || self.boxed_header().header_subtag() != HEADER_SUB_BIN {
+            return None;
+        }
+        let word = |i| unsafe { *self.ptr().add(i) };
+        Some((
+            word(1).small_val() as usize,
+            word(2).small_val() as usize,
+            word(3),
+        ))
+    }
+
+    pub fn ref_val(self) -> Ref {
+        debug_assert!(self.is_ref());
+        Ref::from_words(std::array::from_fn(|i| {
+            unsafe { *self.ptr().add(1 + i) }.raw()
+        }))
+    }
+
+    /// Erlang term order: number < atom < reference < fun < port < pid < tuple < nil < list <
+    /// binary
+    pub fn compare(self, other: Term) -> Ordering {
+        fn rank(term: Term) -> u8 {
+            match term.tag() {
+                Tag::Small | Tag::Big | Tag::Float => 0,
+                Tag::Atom => 1,
+                Tag::Refer => 2,
+                Tag::Fun => 3,
+                Tag::Port => 4,
+                Tag::Pid => 5,
+                Tag::Tuple => 6,
+                Tag::Nil => 7,
+                Tag::List => 8,
+                Tag::Binary => 9,
+                _ => 10,
+            }
+        }
+
+        if self == other {
+            return Ordering::Equal;
+        }
+        match (self.tag(), other.tag()) {
+            (Tag::Small, Tag::Small) => self.small_val().cmp(&other.small_val()),
+            (Tag::Small | Tag::Big, Tag::Small | Tag::Big) => {
+                self.int_val().unwrap().cmp(&other.int_val().unwrap())
+            }
+            (Tag::Float, Tag::Float) => self.float_val().partial_cmp(&other.float_val()).unwrap(),
+            // Compared by value, so 1 == 1.0
+            (Tag::Float, Tag::Small | Tag::Big) => {
+                compare_float_int(self.float_val(), other.int_val().unwrap())
+            }
+            (Tag::Small | Tag::Big, Tag::Float) => {
+                compare_float_int(other.float_val(), self.int_val().unwrap()).reverse()
+            }
+            (Tag::Atom, Tag::Atom) => atom::compare(self.atom_val(), other.atom_val()),
+            (Tag::Refer, Tag::Refer) => self.ref_val().cmp(&other.ref_val()),
+            (Tag::Fun, Tag::Fun) => self
+                .fun_module()
+                .compare(other.fun_module())
+                .then_with(|| self.fun_entry().cmp(&other.fun_entry()))
+                .then_with(|| {
+                    let (a, b) = (self.fun_env(), other.fun_env());
+                    a.len().cmp(&b.len()).then_with(|| {
+                        a.iter()
+                            .zip(&b)
+                            .map(|(a, b)| a.compare(*b))
+                            .find(|ord| ord.is_ne())
+                            .unwrap_or(Ordering::Equal)
+                    })
+                }),
+            (Tag::Port, Tag::Port) => self.port_val().cmp(&other.port_val()),
+            (Tag::Binary, Tag::Binary) => self.with_bytes(|a| other.with_bytes(|b| a.cmp(b))),
+            (Tag::Pid, Tag::Pid) => self.pid_val().cmp(&other.pid_val()),
+            (Tag::Tuple, Tag::Tuple) => {
+                self.tuple_arity().cmp(&other.tuple_arity()).then_with(|| {
+                    (1..=self.tuple_arity())
+                        .map(|i| self.element(i).compare(other.element(i)))
+                        .find(|ord| ord.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                })
+            }
+            (Tag::List, Tag::List) => {
+                let (mut a, mut b) = (self, other);
+                while a.is_list() && b.is_list() {
+                    match a.hd().compare(b.hd()) {
+                        Ordering::Equal => (a, b) = (a.tl(), b.tl()),
+                        ord => return ord,
+                    }
+                }
+                a.compare(b)
+            }
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+
+    pub fn hd(self) -> Term {
+        debug_assert!(self.is_list());
+        unsafe { *self.ptr() }
+    }
+
+    pub fn tl(self) -> Term {
+        debug_assert!(self.is_list());
+        unsafe { *self.ptr().add(1) }
+    }
+
+    pub fn expect_small(self) -> i64 {
+        if self.is_small() {
+            self.small_val()
+        } else {
+            panic!("expected int, got {self:?}");
+        }
+    }
+
+    pub fn expect_float(self) -> f64 {
+        if self.is_float() {
+            self.float_val()
+        } else {
+            panic!("expected float, got {self:?}");
+        }
+    }
+
+    pub fn expect_tuple(self) -> Term {
+        if self.is_tuple() {
+            self
+        } else {
+            panic!("expected tuple, got {self:?}");
+        }
+    }
+
+    pub fn expect_list(self) -> Term {
+        if self.is_list() {
+            self
+        } else {
+            panic!("expected nonempty list, got {self:?}");
+        }
+    }
+
+    pub fn expect_pid(self) -> PID {
+        if self.is_pid() {
+            self.pid_val()
+        } else {
+            panic!("expected pid, got {self:?}");
+        }
+    }
+}
+
+impl Debug for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.tag() {
+            Tag::Small => write!(f, "{}", self.small_val()),
+            Tag::Big => write!(f, "{}", self.big_val()),
+            Tag::Float => write!(f, "{:?}", self.float_val()),
+            Tag::Atom => write!(f, "{}", atom::name(self.atom_val())),
+            Tag::Pid => write!(f, "{:?}", self.pid_val()),
+            Tag::Refer => write!(f, "{:?}", self.ref_val()),
+            Tag::Fun => write!(
+                f,
+                "#Fun<{:?}.{}.{}>",
+                self.fun_module(),
+                self.fun_entry(),
+                self.fun_arity()
+            ),
+            Tag::Nil => write!(f, "[]"),
+            Tag::Binary => self.with_bytes(|bytes| {
+                let bytes: Vec<_> = bytes.iter().map(u8::to_string).collect();
+                write!(f, "<<{}>>", bytes.join(","))
+            }),
+            Tag::Port => write!(f, "#Port<{}>", self.port_val()),
+            Tag::Catch => write!(f, "#Catch<{}>", self.catch_val()),
+            Tag::CP0 | Tag::CP4 | Tag::CP8 | Tag::CP12 => write!(f, "#CP<{}>", self.cp_val()),
+            tag => write!(f, "{tag:?}({:#x})", self.0),
+        }
+    }
+}
+
+/// Exact comparison, converting `int` to a float could round it
+fn compare_float_int(float: f64, int: BigInt) -> Ordering {
+    let floor = float.floor();
+    BigInt::from_f64(floor)
+        .unwrap()
+        .cmp(&int)
+        .then(if float > floor {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        })
+}
+
+impl TryFrom<&DataObject> for Term {
+    type Error = String;
+
+    fn try_from(value: &DataObject) -> Result<Self, Self::Error> {
+        match value {
+            DataObject::Small(v) => Ok(Term::small(*v)),
+            DataObject::Atom(atom) => Ok(Term::atom(atom.index())),
+            DataObject::Pid(pid) => Ok(Term::pid(pid)),
+            DataObject::Nil => Ok(Term::NIL),
+            DataObject::IC(ip) => Ok(Term::cp(*ip)),
+            _ => Err(format!("cannot convert {value:?} to an immediate term")),
+        }
+    }
+}
+
+impl From<Term> for DataObject {
+    fn from(term: Term) -> Self {
+        match term.tag() {
+            Tag::Small => DataObject::Small(term.small_val()),
+            Tag::Atom => DataObject::Atom(Atom::from_index(term.atom_val())),
+            Tag::Pid => DataObject::Pid(term.pid_val()),
+            Tag::Nil => DataObject::Nil,
+            Tag::CP0 | Tag::CP4 | Tag::CP8 | Tag::CP12 => DataObject::IC(term.cp_val()),
+            Tag::Big => DataObject::Big(term.big_val()),
+            Tag::Float => DataObject::Float(term.float_val()),
+            Tag::Refer => DataObject::Refer(term.ref_val()),
+            Tag::Fun => DataObject::Fun {
+                module: Atom::from_index(term.fun_module().atom_val()),
+                entry: term.fun_entry(),
+                arity: term.fun_arity(),
+                env: term.fun_env().into_iter().map(DataObject::from).collect(),
+            },
+            Tag::Port => DataObject::Port,
+            Tag::Tuple => DataObject::Tuple(
+                (1..=term.tuple_arity())
+                    .map(|i| DataObject::from(term.element(i)))
+                    .collect(),
+            ),
+            Tag::List => {
+                // Long lists are common, so walk them iteratively rather than recursing on the tail
+                let mut elems = Vec::new();
+                let mut tail = term;
+                while tail.is_list() {
+                    elems.push(DataObject::from(tail.hd()));
+                    tail = tail.tl();
+                }
+                DataObject::List(elems, Box::new(DataObject::from(tail)))
+            }
+            Tag::Arityval => DataObject::Arityval(term.header_arity()),
+            Tag::Moved => DataObject::Moved,
+            Tag::Catch => DataObject::Catch,
+            Tag::Thing => DataObject::Thing,
+            Tag::Binary if term.is_refc_binary() => DataObject::Binary(term.refc_data()),
+            Tag::Binary => DataObject::Binary(Arc::new(term.with_bytes(<[u8]>::to_vec))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Ordering, sync::Arc, thread};
+
+    use num_bigint::BigInt;
+
+    use crate::mem::{
+        DataObject, PID, REF_WORDS, Ref, atom::Atom, heap::Heap, next_creation, set_ref_scheduler,
+    };
+
+    use super::{HEADER_FLOAT, MAX_SMALL, MIN_SMALL, Tag, Term};
+
+    fn round_trip(data: DataObject) {
+        let term = Term::try_from(&data).unwrap();
+        assert_eq!(DataObject::from(term), data);
+    }
+
+    #[test]
+    fn immediates() {
+        round_trip(DataObject::Small(0));
+        round_trip(DataObject::Small(MAX_SMALL));
+        round_trip(DataObject::Small(MIN_SMALL));
+        round_trip(DataObject::atom("ok"));
+        round_trip(DataObject::Pid(PID::new(3, 12345)));
+        round_trip(DataObject::Nil);
+        round_trip(DataObject::IC(42));
+
+        assert_eq!(Term::small(MIN_SMALL).small_val(), MIN_SMALL);
+        assert_eq!(Term::small(MAX_SMALL).small_val(), MAX_SMALL);
+        assert_eq!(Term::small(-1).small_val(), -1);
+        assert!(Term::try_from(&DataObject::Tuple(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn bignums() {
+        let mut heap = Heap::new(32);
+        let mut build = |n: BigInt| {
+            let data = DataObject::from(n);
+            let term = heap.build(&data);
+            assert_eq!(DataObject::from(term), data);
+            term
+        };
+        let huge = BigInt::from(3).pow(100);
+        let ascending = [
+            build(-&huge),
+            build(BigInt::from(MIN_SMALL) - 1),
+            build(MIN_SMALL.into()),
+            build(0.into()),
+            build(MAX_SMALL.into()),
+            build(BigInt::from(MAX_SMALL) + 1),
+            build(huge.clone()),
+        ];
+        assert!(ascending[0].is_big() && ascending[1].is_big());
+        assert!(ascending[2].is_small() && ascending[4].is_small());
+        assert!(ascending[5].is_big() && ascending[6].is_big());
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(a.compare(*b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+        // Equal bignums are different objects but compare equal
+        assert_eq!(build(huge.clone()).compare(ascending[6]), Ordering::Equal);
+        assert_eq!(format!("{:?}", ascending[6]), huge.to_string());
+    }
+
+    #[test]
+    fn floats() {
+        let mut heap = Heap::new(64);
+        let mut build = |data: DataObject| {
+            let term = heap.build(&data);
+            assert_eq!(DataObject::from(term), data);
+            term
+        };
+        let ascending = [
+            build(DataObject::Float(-1e30)),
+            build(DataObject::from(BigInt::from(MIN_SMALL) - 1)),
+            build(DataObject::Float(-1.5)),
+            build(DataObject::Small(-1)),
+            build(DataObject::Float(0.5)),
+            build(DataObject::Small(1)),
+            build(DataObject::Float(1.5)),
+            build(DataObject::from(BigInt::from(MAX_SMALL) + 1)),
+            build(DataObject::Float(1e30)),
+        ];
+        assert!(ascending[0].is_float() && !ascending[0].is_integer());
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(a.compare(*b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+        // Integers and floats with the same value are equal
+        let one = build(DataObject::Float(1.0));
+        assert_eq!(one.compare(ascending[5]), Ordering::Equal);
+        let big = BigInt::from(1) << 70u32;
+        let float = build(DataObject::Float(2f64.powi(70)));
+        assert_eq!(float.compare(build(DataObject::Big(big))), Ordering::Equal);
+        assert_eq!(format!("{:?}", ascending[2]), "-1.5");
+    }
+
+    #[test]
+    fn binaries() {
+        let mut heap = Heap::new(64);
+        let mut build = |bytes: &[u8]| {
+            let data = DataObject::Binary(Arc::new(bytes.to_vec()));
+            let term = heap.build(&data);
+            assert_eq!(DataObject::from(term), data);
+            term
+        };
+        let big = vec![1; 200];
+        let ascending = [
+            build(b""),
+            build(&[0]),
+            build(&[1]),
+            build(&big[..50]),
+            build(&big),
+            build(&[1, 2]),
+            build(&[2]),
+        ];
+        assert!(ascending[4].is_refc_binary() && !ascending[3].is_refc_binary());
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(a.compare(*b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+        // Binaries come after everything else
+        let nil = build(b"");
+        assert_eq!(nil.compare(Term::NIL), Ordering::Greater);
+        assert_eq!(format!("{:?}", ascending[5]), "<<1,2>>");
+
+        // Refc binaries convert to objects that share their bytes with the heap
+        let DataObject::Binary(data) = DataObject::from(ascending[4]) else {
+            unreachable!()
+        };
+        assert_eq!(Arc::strong_count(&data), 2);
+    }
+
+    #[test]
+    fn atoms_are_interned() {
+        let a = Term::try_from(&DataObject::atom("hello")).unwrap();
+        let b = Term::try_from(&DataObject::atom("hello")).unwrap();
+        let c = Term::try_from(&DataObject::atom("world")).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(Term::small(5).tag(), Tag::Small);
+        assert_eq!(Term::NIL.tag(), Tag::Nil);
+        assert_eq!(Term::atom(0).tag(), Tag::Atom);
+        assert_eq!(Term::pid(&PID::new(0, 0)).tag(), Tag::Pid);
+        assert_eq!(Term::catch(1).tag(), Tag::Catch);
+        assert_eq!(Term::cp(0).tag(), Tag::CP0);
+        assert_eq!(Term::cp(1).tag(), Tag::CP4);
+        assert_eq!(Term::cp(2).tag(), Tag::CP8);
+        assert_eq!(Term::cp(3).tag(), Tag::CP12);
+        assert_eq!(Term::arityval(2).tag(), Tag::Arityval);
+
+        let heap = [Term::arityval(2), Term::small(1), Term::NIL];
+        let tuple = Term::boxed(&heap[0]);
+        assert_eq!(tuple.tag(), Tag::Tuple);
+        assert_eq!(tuple.ptr() as *const Term, &heap[0] as *const Term);
+        assert_eq!(Term::list(&heap[1]).tag(), Tag::List);
+
+        let float = [Term::header(HEADER_FLOAT, 1), Term::from_raw(0)];
+        assert_eq!(Term::boxed(&float[0]).tag(), Tag::Float);
+
+        let moved = Term::moved(&heap[1]);
+        assert_eq!(moved.tag(), Tag::Moved);
+        assert_eq!(moved.moved_val() as *const Term, &heap[1] as *const Term);
+    }
+
+    #[test]
+    fn references() {
+        let refs = |creation, scheduler| {
+            thread::spawn(move || {
+                set_ref_scheduler(creation, scheduler);
+                [Ref::new(), Ref::new()]
+            })
+            .join()
+            .unwrap()
+        };
+        let [a, b] = refs(0, 0);
+        let [c, _] = refs(0, 1);
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+        assert!(a < b && b < c);
+        assert_eq!(format!("{a:?}"), "#Ref<0.1.0.0>");
+        assert_eq!(format!("{c:?}"), "#Ref<0.2.0.0>");
+        // Schedulers with the same id in another VM make different references
+        let [d, _] = refs(next_creation(), 0);
+        assert_ne!(a, d);
+
+        let mut heap = Heap::new(1 + REF_WORDS);
+        let term = heap.reference(b);
+        assert_eq!(term.ref_val(), b);
+        assert_eq!(format!("{term:?}"), "#Ref<0.1.0.1>");
+    }
+
+    #[test]
+    fn term_order() {
+        let mut heap = Heap::new(64);
+        let mut build = |data| heap.build(&data);
+        let atom = DataObject::atom;
+        let ascending = [
+            build(DataObject::Small(2)),
+            build(DataObject::Small(10)),
+            build(atom("abc")),
+            build(atom("b")),
+            build(DataObject::Refer(Ref::new())),
+            build(DataObject::Fun {
+                module: Atom::new("a"),
+                entry: 0,
+                arity: 0,
+                env: Vec::new(),
+            }),
+            build(DataObject::Fun {
+                module: Atom::new("a"),
+                entry: 0,
+                arity: 0,
+                env: vec![DataObject::Small(1)],
+            }),
+            build(DataObject::Fun {
+                module: Atom::new("b"),
+                entry: 0,
+                arity: 0,
+                env: Vec::new(),
+            }),
+            build(DataObject::Pid(PID::new(1, 0))),
+            build(DataObject::Pid(PID::new(0, 1))),
+            build(DataObject::Tuple(vec![atom("z")])),
+            build(DataObject::Tuple(vec![atom("a"), atom("a")])),
+            build(DataObject::Tuple(vec![atom("a"), atom("b")])),
+            build(DataObject::Nil),
+            build(DataObject::list(
+                vec![DataObject::Small(1)],
+                DataObject::Nil,
+            )),
+            build(DataObject::list(
+                vec![DataObject::Small(1), DataObject::Small(0)],
+                DataObject::Nil,
+            )),
+            build(DataObject::list(
+                vec![DataObject::Small(2)],
+                DataObject::Nil,
+            )),
+        ];
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(a.compare(*b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+
+        let tuple = build(DataObject::Tuple(vec![atom("a"), atom("b")]));
+        assert_eq!(tuple.compare(ascending[12]), Ordering::Equal);
+    }
+}
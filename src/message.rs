@@ -1,36 +1,235 @@
-use std::sync::Arc;
+use std::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
 
-use crate::DataObject;
+use crate::{
+    DataObject,
+    mem::{copy, heap::Heap, term::Term},
+};
 
 #[derive(Debug)]
 pub struct Mailbox {
-    msgs: Vec<DataObject>,
-    save: Option<usize>,
+    msgs: Vec<Term>,
+    /// Index of the next message `loop_rec` looks at. Messages before it have already been
+    /// rejected by the current receive.
+    save: usize,
+    /// Saved by `recv_mark` along with its label so that the `recv_set` for the same receive can
+    /// skip messages that arrived before it
+    mark: Option<(usize, usize)>,
+    /// Heap fragments received messages live in until the next garbage collection moves them
+    /// onto the process heap
+    fragments: Vec<Heap>,
 }
 
 impl Mailbox {
     pub fn new() -> Self {
         Self {
             msgs: Vec::new(),
-            save: None,
+            save: 0,
+            mark: None,
+            fragments: Vec::new(),
         }
     }
 
-    pub fn add_msg(&mut self, msg: DataObject) {
-        self.msgs.push(msg);
+    /// Message at the save pointer
+    pub fn peek(&self) -> Option<Term> {
+        self.msgs.get(self.save).copied()
+    }
+
+    /// Moves the save pointer past a message that didn't match
+    pub fn next(&mut self) {
+        self.save += 1;
+    }
+
+    /// Removes the message at the save pointer and starts the next receive from the beginning
+    pub fn remove(&mut self) -> Term {
+        let msg = self.msgs.remove(self.save);
+        self.reset_save();
+        msg
+    }
+
+    pub fn reset_save(&mut self) {
+        self.save = 0;
+        self.mark = None;
+    }
+
+    pub fn mark(&mut self, lbl: usize) {
+        self.mark = Some((lbl, self.msgs.len()));
+    }
+
+    pub fn set_to_mark(&mut self, lbl: usize) {
+        if let Some((mark_lbl, mark)) = self.mark
+            && mark_lbl == lbl
+        {
+            self.save = mark;
+        }
+    }
+
+    /// Drops every message `pred` holds for and starts the next receive from the beginning
+    pub fn remove_matching(&mut self, pred: impl Fn(Term) -> bool) {
+        self.msgs.retain(|msg| !pred(*msg));
+        self.reset_save();
+    }
+
+    /// Adds a message that was sent along with its heap fragment
+    pub fn add_mbuf(&mut self, mbuf: MBuf) {
+        self.msgs.push(mbuf.msg());
+        if mbuf.heap.size() > 0 {
+            self.fragments.push(mbuf.heap);
+        }
+    }
+
+    /// Words in heap fragments, which are only freed by a garbage collection
+    pub fn fragment_words(&self) -> usize {
+        self.fragments.iter().map(Heap::htop).sum()
+    }
+
+    /// Messages point into the process heap or into heap fragments, so they are roots for the
+    /// garbage collector and the fragments are collected along with the young heap
+    pub fn gc_parts(&mut self) -> (&mut [Term], &mut Vec<Heap>) {
+        (&mut self.msgs, &mut self.fragments)
     }
 }
 
-pub type MBuf = Arc<DataObject>;
+/// Message copied out of the sender into a heap fragment of its own, so that sending never
+/// touches the heap of the receiver
+#[derive(Debug)]
+pub struct MBuf {
+    msg: Term,
+    heap: Heap,
+}
 
-// pub struct Mailbox<'a> {
-//     len: usize,
-//     first: &'a Message<'a>,
-//     last: &'a Message<'a>,
-//     save: &'a Message<'a>,
-// }
+impl MBuf {
+    pub fn copy(term: Term) -> Self {
+        let mut heap = Heap::new(copy::size(term));
+        let msg = copy::copy(term, &mut heap);
+        Self { msg, heap }
+    }
 
-// pub struct Message<'a> {
-//     next: &'a Message<'a>,
-//     body: DataObject,
-// }
+    pub fn build(data: &DataObject) -> Self {
+        let mut heap = Heap::new(data.size());
+        let msg = heap.build(data);
+        Self { msg, heap }
+    }
+
+    pub fn msg(&self) -> Term {
+        self.msg
+    }
+}
+
+/// Messages sent to a process that haven't been moved into its [`Mailbox`] yet. Senders push
+/// without taking any lock, the receiving process takes everything at once when it receives.
+#[derive(Debug)]
+pub struct InQueue {
+    /// Most recently sent message first
+    head: AtomicPtr<Node>,
+}
+
+struct Node {
+    msg: MBuf,
+    next: *mut Node,
+}
+
+impl InQueue {
+    pub fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn push(&self, msg: MBuf) {
+        let node = Box::into_raw(Box::new(Node {
+            msg,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: the node isn't visible to anyone else until the exchange succeeds
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Takes all queued messages, oldest first
+    pub fn take_all(&self) -> Vec<MBuf> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut msgs = Vec::new();
+        while !node.is_null() {
+            // SAFETY: swapping the head out gave us sole ownership of the whole list
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            msgs.push(boxed.msg);
+        }
+        msgs.reverse();
+        msgs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl Drop for InQueue {
+    fn drop(&mut self) {
+        self.take_all();
+    }
+}
+
+// SAFETY: nodes are only reachable through the atomic head, see `push` and `take_all`
+unsafe impl Send for InQueue {}
+unsafe impl Sync for InQueue {}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use crate::DataObject;
+
+    use super::{InQueue, MBuf};
+
+    #[test]
+    fn in_queue() {
+        let queue = Arc::new(InQueue::new());
+        assert!(queue.is_empty());
+        let senders: Vec<_> = (0..4)
+            .map(|sender| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        queue.push(MBuf::build(&DataObject::Tuple(vec![
+                            DataObject::Small(sender),
+                            DataObject::Small(i),
+                        ])));
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        let msgs = queue.take_all();
+        assert!(queue.is_empty());
+        assert_eq!(msgs.len(), 4000);
+        // Messages from the same sender stay in order
+        for sender in 0..4 {
+            let sent: Vec<_> = msgs
+                .iter()
+                .filter_map(|mbuf| match DataObject::from(mbuf.msg()) {
+                    DataObject::Tuple(elems) if elems[0] == DataObject::Small(sender) => {
+                        Some(elems[1].expect_int())
+                    }
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(sent, (0..1000).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Loaded code. A module is loaded once and shared by every process running it, processes only
+//! keep an instruction pointer into it.
+//!
+//! Like in ERTS, calls to other modules go through export entries. The loader resolves every
+//! function a module calls to the export entry for it, and the entry is filled in once the module
+//! exporting the function is loaded, so modules can be loaded in any order.
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{Instruction, instr::ExtFunc, parsing::Source};
+
+/// `module:function/arity`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct MFA {
+    pub module: String,
+    pub function: String,
+    pub arity: usize,
+}
+
+/// Where calls to an exported function go
+#[derive(Default)]
+pub struct Export {
+    target: RwLock<Option<(Arc<Module>, usize)>>,
+}
+
+impl Export {
+    /// Module and entry point of the function, `None` until a module exporting it is loaded
+    pub fn target(&self) -> Option<(Arc<Module>, usize)> {
+        self.target.read().unwrap().clone()
+    }
+}
+
+impl Debug for Export {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self.target.read().unwrap() {
+            Some((module, entry)) => write!(f, "Export({}, {entry})", module.name()),
+            None => write!(f, "Export(undefined)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Module {
+    name: String,
+    instrs: Vec<Instruction>,
+    /// Entry points by function name and arity, found through the `func_info` instructions
+    functions: HashMap<(String, usize), usize>,
+    exports: Vec<(String, usize)>,
+    /// Export entries of the functions the `call_ext` instructions call, by `ExtFunc::Import`
+    imports: Vec<Arc<Export>>,
+}
+
+impl Module {
+    /// Module that isn't known to any code server, so calls to other modules fail with `undef`
+    pub fn new(name: &str, instrs: Vec<Instruction>) -> Arc<Self> {
+        Self::link(
+            Source {
+                name: name.to_string(),
+                exports: Vec::new(),
+                instrs,
+            },
+            |_| Arc::default(),
+        )
+    }
+
+    /// Replaces the functions named in `call_ext` instructions with indices into the import
+    /// table, which holds the export entries `export` returns
+    fn link(source: Source, mut export: impl FnMut(&MFA) -> Arc<Export>) -> Arc<Self> {
+        let mut imports = Vec::new();
+        let mut import_indices = HashMap::new();
+        let mut instrs = source.instrs;
+        for instr in &mut instrs {
+            if let Instruction::CallExt { func }
+            | Instruction::CallExtOnly { func }
+            | Instruction::CallExtLast { func, .. } = instr
+                && let ExtFunc::Name(mfa) = func
+            {
+                let i = *import_indices.entry(mfa.clone()).or_insert_with(|| {
+                    imports.push(export(mfa));
+                    imports.len() - 1
+                });
+                *func = ExtFunc::Import(i);
+            }
+        }
+
+        let functions = instrs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| match instr {
+                Instruction::FuncInfo {
+                    function, arity, ..
+                } => Some(((function.clone(), *arity), i + 1)),
+                _ => None,
+            })
+            .collect();
+        Arc::new(Self {
+            name: source.name,
+            instrs,
+            functions,
+            exports: source.exports,
+            imports,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn instrs(&self) -> &[Instruction] {
+        &self.instrs
+    }
+
+    /// Instruction `function/arity` starts at
+    pub fn entry(&self, function: &str, arity: usize) -> Option<usize> {
+        self.functions.get(&(function.to_string(), arity)).copied()
+    }
+
+    pub fn import(&self, i: usize) -> &Export {
+        &self.imports[i]
+    }
+}
+
+/// Loaded modules and the export entries of the functions they call
+#[derive(Debug, Default)]
+pub struct CodeServer {
+    modules: RwLock<HashMap<String, Arc<Module>>>,
+    exports: Mutex<HashMap<MFA, Arc<Export>>>,
+}
+
+impl CodeServer {
+    /// Export entry for `mfa`, which is created the first time it is asked for
+    fn export(&self, mfa: &MFA) -> Arc<Export> {
+        self.exports
+            .lock()
+            .unwrap()
+            .entry(mfa.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Loads `source`, replacing an older version of the module. Fails without loading anything
+    /// if the module exports a function it doesn't define.
+    pub fn load(&self, source: Source) -> Result<Arc<Module>, String> {
+        let module = Module::link(source, |mfa| self.export(mfa));
+        let entries = module
+            .exports
+            .iter()
+            .map(|(function, arity)| {
+                let entry = module
+                    .entry(function, *arity)
+                    .ok_or_else(|| format!("{}:{function}/{arity} is not defined", module.name))?;
+                let mfa = MFA {
+                    module: module.name.clone(),
+                    function: function.clone(),
+                    arity: *arity,
+                };
+                Ok((mfa, entry))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for (mfa, entry) in entries {
+            *self.export(&mfa).target.write().unwrap() = Some((module.clone(), entry));
+        }
+        self.modules
+            .write()
+            .unwrap()
+            .insert(module.name.clone(), module.clone());
+        Ok(module)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Module>> {
+        self.modules.read().unwrap().get(name).cloned()
+    }
+
+    /// Module and entry point of `mfa` if it is exported
+    pub fn lookup(&self, mfa: &MFA) -> Option<(Arc<Module>, usize)> {
+        self.exports.lock().unwrap().get(mfa)?.target()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{Instruction, instr::ExtFunc, parse_module, parse_str};
+
+    use super::{CodeServer, MFA, Module};
+
+    #[test]
+    fn entry_points() {
+        let module = Module::new(
+            "test",
+            parse_str(
+                "{ret}.
+{func_info, test, f, 0}.
+{label, 1}.
+{ret}.
+{func_info, test, f, 1}.
+{jmp, 1}.",
+            ),
+        );
+        assert_eq!(module.name(), "test");
+        assert_eq!(module.instrs().len(), 5);
+        assert_eq!(module.entry("f", 0), Some(2));
+        assert_eq!(module.entry("f", 1), Some(4));
+        assert_eq!(module.entry("f", 2), None);
+        assert_eq!(module.entry("g", 0), None);
+    }
+
+    #[test]
+    fn undefined_export() {
+        let code = CodeServer::default();
+        let res = code.load(parse_module(
+            "{module, bad}.
+{exports, [{f, 0}, {g, 0}]}.
+{func_info, bad, f, 0}.
+{ret}.",
+        ));
+        assert_eq!(res.unwrap_err(), "bad:g/0 is not defined");
+        // Nothing of the module is loaded
+        assert!(code.get("bad").is_none());
+        let f = MFA {
+            module: "bad".to_string(),
+            function: "f".to_string(),
+            arity: 0,
+        };
+        assert!(code.lookup(&f).is_none());
+    }
+
+    #[test]
+    fn imports() {
+        let code = CodeServer::default();
+        // Loaded before the module it calls
+        let a = code
+            .load(parse_module(
+                "{module, a}.
+{exports, [{f, 0}]}.
+{func_info, a, f, 0}.
+{call_ext, 1, {extfunc, b, g, 1}}.
+{call_ext_only, 1, {extfunc, b, g, 1}}.
+{call_ext_last, 0, {extfunc, b, h, 0}, 1}.",
+            ))
+            .unwrap();
+        let imports: Vec<_> = a.instrs()[1..]
+            .iter()
+            .map(|instr| match instr {
+                Instruction::CallExt { func }
+                | Instruction::CallExtOnly { func }
+                | Instruction::CallExtLast { func, .. } => func.clone(),
+                _ => panic!("expected call_ext"),
+            })
+            .collect();
+        assert_eq!(
+            imports,
+            [ExtFunc::Import(0), ExtFunc::Import(0), ExtFunc::Import(1)]
+        );
+        assert!(a.import(0).target().is_none());
+
+        let b = code
+            .load(parse_module(
+                "{module, b}.
+{exports, [{g, 1}]}.
+{func_info, b, g, 1}.
+{ret}.
+{func_info, b, h, 0}.
+{ret}.",
+            ))
+            .unwrap();
+        let (module, entry) = a.import(0).target().unwrap();
+        assert!(Arc::ptr_eq(&module, &b));
+        assert_eq!(entry, 1);
+        // Only exported functions can be called from other modules
+        assert!(a.import(1).target().is_none());
+
+        let mfa = |module: &str, function: &str, arity| MFA {
+            module: module.to_string(),
+            function: function.to_string(),
+            arity,
+        };
+        assert_eq!(code.lookup(&mfa("a", "f", 0)).unwrap().1, 1);
+        assert!(code.lookup(&mfa("b", "h", 0)).is_none());
+        assert!(code.lookup(&mfa("c", "f", 0)).is_none());
+        assert!(Arc::ptr_eq(&code.get("b").unwrap(), &b));
+    }
+}
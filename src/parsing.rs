@@ -1,4 +1,11 @@
-use crate::{DataObject, Instruction, PID, Reg};
+use std::sync::Arc;
+
+use crate::{
+    DataObject, Instruction, PID, Reg,
+    bif::Bif,
+    instr::{ExtFunc, Src},
+    module::MFA,
+};
 
 lrlex::lrlex_mod!("byte.l");
 lrpar::lrpar_mod!("byte.y");
@@ -6,10 +13,45 @@ lrpar::lrpar_mod!("byte.y");
 pub use byte_y::{Item, List, Prog};
 
 type Label = (usize, usize);
+
+const REGS: [&str; 9] = ["x", "y", "fr", "Htop", "E", "I", "FP", "CP", "fcalls"];
+
+fn get_src(item: &Item) -> Src {
+    match item {
+        Item::List(list) if matches!(list.first(), Some(Item::Atom(a)) if REGS.contains(&&a[..])) => {
+            Src::Reg(Reg::from(list))
+        }
+        _ => Src::Lit(DataObject::from(item)),
+    }
+}
+
+/// Index of a `{fr, N}` float register
+fn get_float_reg(item: &Item) -> usize {
+    match Reg::from(item.expect_list()) {
+        Reg::FR(i) => i,
+        reg => panic!("expected float register, got {reg:?}"),
+    }
+}
+
 fn get_label(labels: &[Label], item: &Item) -> usize {
     let n = item.expect_num();
     labels.iter().find(|(name, _)| name == &n).unwrap().1
 }
+
+/// `{call_ext, Arity, {extfunc, Module, Function, Arity}, ...}`
+fn get_ext_func(list: &List) -> ExtFunc {
+    let func = list[2].expect_list();
+    assert_eq!(func.len(), 4);
+    assert_eq!(func[0].expect_atom(), "extfunc");
+    let arity = func[3].expect_num();
+    assert_eq!(list[1].expect_num(), arity);
+    ExtFunc::Name(MFA {
+        module: func[1].expect_atom().to_string(),
+        function: func[2].expect_atom().to_string(),
+        arity,
+    })
+}
+
 impl From<(&[Label], &List)> for Instruction {
     fn from(value: (&[Label], &Vec<Item>)) -> Self {
         let (labels, list) = value;
@@ -33,6 +75,98 @@ impl From<(&[Label], &List)> for Instruction {
                     let stack_need = list[1].expect_num();
                     Instruction::Allocate { stack_need }
                 }
+                "test_heap" => {
+                    assert_eq!(list.len(), 2);
+                    let need = list[1].expect_num();
+                    Instruction::TestHeap { need }
+                }
+                "bif" => {
+                    assert!(list.len() >= 3);
+                    let name = list[1].expect_atom();
+                    let args: Vec<_> = list[2..list.len() - 1]
+                        .iter()
+                        .map(|arg| Reg::from(arg.expect_list()))
+                        .collect();
+                    let bif = Bif::from_name(name, args.len())
+                        .unwrap_or_else(|| panic!("unknown bif {name}/{}", args.len()));
+                    let ret = Reg::from(list[list.len() - 1].expect_list());
+                    Instruction::Bif { bif, args, ret }
+                }
+                "gc_bif" => {
+                    // {gc_bif, Name, Fail, Live, [Args], Dst}. Live is ignored since all the X
+                    // registers are garbage collection roots.
+                    assert_eq!(list.len(), 6);
+                    let name = list[1].expect_atom();
+                    let fail = match list[2].expect_num() {
+                        0 => None,
+                        _ => Some(get_label(labels, &list[2])),
+                    };
+                    let Item::Bracket(args, None) = &list[4] else {
+                        panic!("expected list of arguments, got {:?}", list[4]);
+                    };
+                    let args: Vec<_> = args.iter().map(get_src).collect();
+                    let bif = Bif::from_name(name, args.len())
+                        .unwrap_or_else(|| panic!("unknown bif {name}/{}", args.len()));
+                    let ret = Reg::from(list[5].expect_list());
+                    Instruction::GcBif {
+                        bif,
+                        fail,
+                        args,
+                        ret,
+                    }
+                }
+                "put_tuple2" => {
+                    assert_eq!(list.len(), 3);
+                    let dest = Reg::from(list[1].expect_list());
+                    let elems = list[2].expect_list();
+                    assert_eq!(elems[0].expect_atom(), "list");
+                    let elems = match &elems[1..] {
+                        // {list, [A, B]} like BEAM or {list, A, B}
+                        [Item::Bracket(elems, None)] => elems.iter().map(get_src).collect(),
+                        elems => elems.iter().map(get_src).collect(),
+                    };
+                    Instruction::PutTuple2 { dest, elems }
+                }
+                "put_list" => {
+                    assert_eq!(list.len(), 4);
+                    let hd = get_src(&list[1]);
+                    let tl = get_src(&list[2]);
+                    let dest = Reg::from(list[3].expect_list());
+                    Instruction::PutList { hd, tl, dest }
+                }
+                "get_list" => {
+                    assert_eq!(list.len(), 4);
+                    let src = Reg::from(list[1].expect_list());
+                    let hd = Reg::from(list[2].expect_list());
+                    let tl = Reg::from(list[3].expect_list());
+                    Instruction::GetList { src, hd, tl }
+                }
+                "get_hd" => {
+                    assert_eq!(list.len(), 3);
+                    let src = Reg::from(list[1].expect_list());
+                    let dest = Reg::from(list[2].expect_list());
+                    Instruction::GetHd { src, dest }
+                }
+                "get_tl" => {
+                    assert_eq!(list.len(), 3);
+                    let src = Reg::from(list[1].expect_list());
+                    let dest = Reg::from(list[2].expect_list());
+                    Instruction::GetTl { src, dest }
+                }
+                "get_tuple_element" => {
+                    assert_eq!(list.len(), 4);
+                    let src = Reg::from(list[1].expect_list());
+                    let index = list[2].expect_num();
+                    let dest = Reg::from(list[3].expect_list());
+                    Instruction::GetTupleElement { src, index, dest }
+                }
+                "set_tuple_element" => {
+                    assert_eq!(list.len(), 4);
+                    let elem = get_src(&list[1]);
+                    let tuple = Reg::from(list[2].expect_list());
+                    let index = list[3].expect_num();
+                    Instruction::SetTupleElement { elem, tuple, index }
+                }
                 "is_lt" => {
                     assert_eq!(list.len(), 4);
                     let lbl = get_label(labels, &list[1]);
@@ -67,6 +201,103 @@ impl From<(&[Label], &List)> for Instruction {
                     let arg = Reg::from(list[2].expect_list());
                     Instruction::IsInteger { lbl, arg }
                 }
+                "is_float" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsFloat { lbl, arg }
+                }
+                "fmove" => {
+                    assert_eq!(list.len(), 3);
+                    let src = get_src(&list[1]);
+                    let dest = Reg::from(list[2].expect_list());
+                    assert!(
+                        matches!(src, Src::Reg(Reg::FR(_))) != matches!(dest, Reg::FR(_)),
+                        "fmove moves between a float register and a term"
+                    );
+                    Instruction::FMove { src, dest }
+                }
+                "fconv" => {
+                    assert_eq!(list.len(), 3);
+                    let src = get_src(&list[1]);
+                    let dest = get_float_reg(&list[2]);
+                    Instruction::FConv { src, dest }
+                }
+                "fadd" | "fsub" | "fmul" | "fdiv" => {
+                    assert_eq!(list.len(), 4);
+                    let arg0 = get_float_reg(&list[1]);
+                    let arg1 = get_float_reg(&list[2]);
+                    let dest = get_float_reg(&list[3]);
+                    match &instr[..] {
+                        "fadd" => Instruction::FAdd { arg0, arg1, dest },
+                        "fsub" => Instruction::FSub { arg0, arg1, dest },
+                        "fmul" => Instruction::FMul { arg0, arg1, dest },
+                        _ => Instruction::FDiv { arg0, arg1, dest },
+                    }
+                }
+                "fnegate" => {
+                    assert_eq!(list.len(), 3);
+                    let arg = get_float_reg(&list[1]);
+                    let dest = get_float_reg(&list[2]);
+                    Instruction::FNegate { arg, dest }
+                }
+                "fclearerror" => {
+                    assert_eq!(list.len(), 1);
+                    Instruction::FClearError
+                }
+                "fcheckerror" => {
+                    assert_eq!(list.len(), 1);
+                    Instruction::FCheckError
+                }
+                "is_reference" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsReference { lbl, arg }
+                }
+                "is_binary" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsBinary { lbl, arg }
+                }
+                "is_bitstr" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsBitstring { lbl, arg }
+                }
+                "is_tuple" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsTuple { lbl, arg }
+                }
+                "test_arity" => {
+                    assert_eq!(list.len(), 4);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    let arity = list[3].expect_num();
+                    Instruction::TestArity { lbl, arg, arity }
+                }
+                "is_list" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsList { lbl, arg }
+                }
+                "is_nonempty_list" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsNonemptyList { lbl, arg }
+                }
+                "is_nil" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let arg = Reg::from(list[2].expect_list());
+                    Instruction::IsNil { lbl, arg }
+                }
                 "jmp" => {
                     assert_eq!(list.len(), 2);
                     let lbl = get_label(labels, &list[1]);
@@ -81,22 +312,87 @@ impl From<(&[Label], &List)> for Instruction {
                     let ip = get_label(labels, &list[1]);
                     Instruction::Call { ip }
                 }
-                "spawn" => {
-                    assert_eq!(list.len(), 2);
-                    let instrs = list[1]
-                        .expect_list()
-                        .iter()
-                        .map(|l| Instruction::from((labels, l.expect_list())))
-                        .collect();
-                    Instruction::Spawn { instrs }
+                "call_ext" => {
+                    assert_eq!(list.len(), 3);
+                    let func = get_ext_func(list);
+                    Instruction::CallExt { func }
+                }
+                "call_ext_only" => {
+                    assert_eq!(list.len(), 3);
+                    let func = get_ext_func(list);
+                    Instruction::CallExtOnly { func }
+                }
+                "call_ext_last" => {
+                    assert_eq!(list.len(), 4);
+                    let func = get_ext_func(list);
+                    let dealloc = list[3].expect_num();
+                    Instruction::CallExtLast { func, dealloc }
+                }
+                "func_info" => {
+                    assert_eq!(list.len(), 4);
+                    let module = list[1].expect_atom().to_string();
+                    let function = list[2].expect_atom().to_string();
+                    let arity = list[3].expect_num();
+                    Instruction::FuncInfo {
+                        module,
+                        function,
+                        arity,
+                    }
+                }
+                "make_fun" => {
+                    assert_eq!(list.len(), 4);
+                    let lbl = get_label(labels, &list[1]);
+                    let arity = list[2].expect_num();
+                    let num_free = list[3].expect_num();
+                    Instruction::MakeFun {
+                        lbl,
+                        arity,
+                        num_free,
+                    }
                 }
                 "send" => {
                     assert_eq!(list.len(), 1);
                     Instruction::Send
                 }
+                "loop_rec" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let dest = Reg::from(list[2].expect_list());
+                    Instruction::LoopRec { lbl, dest }
+                }
+                "loop_rec_end" => {
+                    assert_eq!(list.len(), 2);
+                    let lbl = get_label(labels, &list[1]);
+                    Instruction::LoopRecEnd { lbl }
+                }
+                "remove_message" => {
+                    assert_eq!(list.len(), 1);
+                    Instruction::RemoveMessage
+                }
                 "wait" => {
+                    assert_eq!(list.len(), 2);
+                    let lbl = get_label(labels, &list[1]);
+                    Instruction::Wait { lbl }
+                }
+                "wait_timeout" => {
+                    assert_eq!(list.len(), 3);
+                    let lbl = get_label(labels, &list[1]);
+                    let time = get_src(&list[2]);
+                    Instruction::WaitTimeout { lbl, time }
+                }
+                "timeout" => {
                     assert_eq!(list.len(), 1);
-                    Instruction::Wait
+                    Instruction::Timeout
+                }
+                "recv_mark" => {
+                    assert_eq!(list.len(), 2);
+                    let lbl = get_label(labels, &list[1]);
+                    Instruction::RecvMark { lbl }
+                }
+                "recv_set" => {
+                    assert_eq!(list.len(), 2);
+                    let lbl = get_label(labels, &list[1]);
+                    Instruction::RecvSet { lbl }
                 }
                 _ => panic!("unknown instruction {instr}"),
             }
@@ -112,7 +408,7 @@ impl From<&List> for Reg {
             match &instr[..] {
                 "x" => {
                     assert_eq!(list.len(), 2);
-                    if let Item::Num(x) = list[1] {
+                    if let Item::Num(x) = &list[1] {
                         Reg::X(x.try_into().unwrap())
                     } else {
                         panic!("invalid register");
@@ -120,12 +416,20 @@ impl From<&List> for Reg {
                 }
                 "y" => {
                     assert_eq!(list.len(), 2);
-                    if let Item::Num(x) = list[1] {
+                    if let Item::Num(x) = &list[1] {
                         Reg::Y(x.try_into().unwrap())
                     } else {
                         panic!("invalid register");
                     }
                 }
+                "fr" => {
+                    assert_eq!(list.len(), 2);
+                    if let Item::Num(x) = &list[1] {
+                        Reg::FR(x.try_into().unwrap())
+                    } else {
+                        panic!("invalid register");
+                    }
+                }
                 "Htop" => {
                     assert_eq!(list.len(), 1);
                     Reg::Htop
@@ -161,8 +465,9 @@ impl From<&List> for Reg {
 impl From<&Item> for DataObject {
     fn from(value: &Item) -> Self {
         match value {
-            Item::Num(x) => DataObject::Small(*x),
-            Item::Atom(x) => DataObject::Atom(x.clone()),
+            Item::Num(x) => DataObject::from(x.clone()),
+            Item::Float(x) => DataObject::Float(*x),
+            Item::Atom(x) => DataObject::atom(x),
             Item::List(x) => match x.first().unwrap().expect_atom() {
                 "nil" => {
                     assert_eq!(x.len(), 1);
@@ -172,13 +477,79 @@ impl From<&Item> for DataObject {
                     assert_eq!(x.len(), 3);
                     DataObject::Pid(PID::new(x[1].expect_num(), x[2].expect_num()))
                 }
+                "literal" => {
+                    assert_eq!(x.len(), 2);
+                    literal(&x[1])
+                }
                 _ => todo!(),
             },
+            Item::Bracket(..) | Item::Binary(_) => literal(value),
         }
     }
 }
 
+/// Inside of `{literal, ...}` braces are tuples rather than special syntax
+fn literal(item: &Item) -> DataObject {
+    match item {
+        Item::Num(x) => DataObject::from(x.clone()),
+        Item::Float(x) => DataObject::Float(*x),
+        Item::Atom(x) => DataObject::atom(x),
+        Item::List(x) => DataObject::Tuple(x.iter().map(literal).collect()),
+        Item::Bracket(elems, tail) => DataObject::list(
+            elems.iter().map(literal).collect(),
+            tail.as_deref().map_or(DataObject::Nil, literal),
+        ),
+        Item::Binary(bytes) => DataObject::Binary(Arc::new(bytes.clone())),
+    }
+}
+
+/// Parsed module, ready to be loaded
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    /// Functions other modules can call, by name and arity
+    pub exports: Vec<(String, usize)>,
+    pub instrs: Vec<Instruction>,
+}
+
+/// Parses a module that starts with `{module, Name}.`, optionally followed by
+/// `{exports, [{Function, Arity}, ...]}.`
+pub fn parse_module(s: &str) -> Source {
+    let prog = parse_prog(s);
+    let mut name = None;
+    let mut exports = Vec::new();
+    for line in &prog {
+        match line.first() {
+            Some(Item::Atom(a)) if a == "module" => {
+                assert_eq!(line.len(), 2);
+                name = Some(line[1].expect_atom().to_string());
+            }
+            Some(Item::Atom(a)) if a == "exports" => {
+                assert_eq!(line.len(), 2);
+                let Item::Bracket(funcs, None) = &line[1] else {
+                    panic!("expected list of exports, got {:?}", line[1]);
+                };
+                exports.extend(funcs.iter().map(|func| {
+                    let func = func.expect_list();
+                    assert_eq!(func.len(), 2);
+                    (func[0].expect_atom().to_string(), func[1].expect_num())
+                }));
+            }
+            _ => {}
+        }
+    }
+    Source {
+        name: name.expect("missing module name"),
+        exports,
+        instrs: instructions(&prog),
+    }
+}
+
 pub fn parse_str(s: &str) -> Vec<Instruction> {
+    instructions(&parse_prog(s))
+}
+
+fn parse_prog(s: &str) -> Prog {
     let lexerdef = byte_l::lexerdef();
     let lexer = lexerdef.lexer(s);
     let (res, errs) = byte_y::parse(&lexer);
@@ -186,17 +557,22 @@ pub fn parse_str(s: &str) -> Vec<Instruction> {
         println!("{}", e.pp(&lexer, &byte_y::token_epp));
     }
 
-    let res = res.unwrap().ok().unwrap();
+    res.unwrap().ok().unwrap()
+}
+
+/// Module attributes that aren't instructions
+const DIRECTIVES: [&str; 2] = ["module", "exports"];
 
+fn instructions(prog: &Prog) -> Vec<Instruction> {
     // First pass to find labels
     let mut labels = Vec::new();
     let mut lines = Vec::new();
-    for line in &res {
+    for line in prog {
         if let Item::Atom(a) = &line[0] {
             if a == "label" {
                 let n = line[1].expect_num();
                 labels.push((n, lines.len()));
-            } else {
+            } else if !DIRECTIVES.contains(&&a[..]) {
                 lines.push(line);
             }
         }
@@ -1,19 +1,84 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use crate::{
-    mem::{DataObject, PID},
+    Instruction,
+    mem::{DataObject, PID, Ref},
     vm::Process,
 };
 
 const NUM_FCALLS: usize = 4000;
 
+/// Size of a time slice and what it costs to run each kind of instruction. A process is
+/// preempted once it has used up `budget` reductions.
+#[derive(Debug, Clone, Copy)]
+pub struct Reductions {
+    pub budget: usize,
+    /// Cost of any instruction not listed below
+    pub instr: usize,
+    pub call: usize,
+    /// Cost of BIF calls, including `spawn`
+    pub bif: usize,
+    pub send: usize,
+    /// Charged for every garbage collection on top of the instruction that triggered it
+    pub gc: usize,
+}
+
+impl Default for Reductions {
+    fn default() -> Self {
+        Self {
+            budget: NUM_FCALLS,
+            instr: 1,
+            call: 1,
+            bif: 2,
+            send: 8,
+            gc: 50,
+        }
+    }
+}
+
+impl Reductions {
+    pub fn cost(&self, instr: &Instruction) -> usize {
+        match instr {
+            Instruction::Call { .. }
+            | Instruction::CallExt { .. }
+            | Instruction::CallExtOnly { .. }
+            | Instruction::CallExtLast { .. } => self.call,
+            Instruction::Bif { .. } | Instruction::GcBif { .. } => self.bif,
+            Instruction::Send => self.send,
+            _ => self.instr,
+        }
+    }
+}
+
+/// Number of times a low priority process is passed over before it gets to run, like
+/// `RESCHEDULE_LOW` in ERTS
+const RESCHEDULE_LOW: usize = 8;
+
 #[derive(Debug)]
 #[allow(clippy::upper_case_acronyms, dead_code)]
 pub struct PCB {
     id: DataObject,
     ip: usize,
     fcalls: usize,
+    reductions: Reductions,
     status: State,
+    priority: Priority,
+    /// Times this low priority process has been passed over in the run queue
+    schedule_count: usize,
+    exit_reason: Option<DataObject>,
+    links: Vec<PID>,
+    /// Processes this process monitors
+    monitors: Vec<(Ref, PID)>,
+    /// Processes monitoring this one
+    monitored_by: Vec<(Ref, PID)>,
+    /// Whether exit signals are turned into `{'EXIT', Pid, Reason}` messages
+    trap_exit: bool,
+
+    /// Deadline of the running `wait_timeout` and the instruction to continue at once it passes
+    timer: Option<(Instant, usize)>,
 
     /// Suspend count
     rstatus: usize,
@@ -27,7 +92,16 @@ impl PCB {
             id: DataObject::Pid(id),
             ip: 0,
             fcalls: NUM_FCALLS,
+            reductions: Reductions::default(),
             status: State::Runnable,
+            priority: Priority::Normal,
+            schedule_count: 0,
+            exit_reason: None,
+            links: Vec::new(),
+            monitors: Vec::new(),
+            monitored_by: Vec::new(),
+            trap_exit: false,
+            timer: None,
             rstatus: 0,
             next: None,
         }
@@ -49,18 +123,165 @@ impl PCB {
         self.fcalls
     }
 
-    /// returns true if process is out of time
-    pub fn dec_fcalls(&mut self) -> bool {
+    pub fn reductions(&self) -> &Reductions {
+        &self.reductions
+    }
+
+    /// Also starts a fresh time slice with the new budget
+    pub fn set_reductions(&mut self, reductions: Reductions) {
+        self.reductions = reductions;
+        self.fcalls = reductions.budget;
+    }
+
+    /// Charges `cost` reductions without yielding, the next `dec_fcalls` takes care of that
+    pub fn bump_reductions(&mut self, cost: usize) {
+        self.fcalls = self.fcalls.saturating_sub(cost);
+    }
+
+    /// Charges `cost` reductions. Returns true if process is out of time.
+    pub fn dec_fcalls(&mut self, cost: usize) -> bool {
         assert_eq!(self.status, State::Running);
-        self.fcalls -= 1;
+        self.bump_reductions(cost);
         if self.fcalls == 0 {
             self.status = State::Runnable;
-            self.fcalls = NUM_FCALLS;
+            self.fcalls = self.reductions.budget;
             return true;
         }
         false
     }
 
+    pub fn exit(&mut self, reason: DataObject) {
+        self.status = State::Exiting;
+        self.exit_reason = Some(reason);
+    }
+
+    pub fn exit_reason(&self) -> Option<&DataObject> {
+        self.exit_reason.as_ref()
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        matches!(self.status, State::Exiting)
+    }
+
+    pub fn link(&mut self, pid: PID) {
+        if !self.links.contains(&pid) {
+            self.links.push(pid);
+        }
+    }
+
+    /// Returns whether there was a link to remove
+    pub fn unlink(&mut self, pid: &PID) -> bool {
+        let len = self.links.len();
+        self.links.retain(|link| link != pid);
+        self.links.len() != len
+    }
+
+    pub fn take_links(&mut self) -> Vec<PID> {
+        std::mem::take(&mut self.links)
+    }
+
+    pub fn monitor(&mut self, reference: Ref, pid: PID) {
+        self.monitors.push((reference, pid));
+    }
+
+    /// Returns the monitored process if the monitor was still active
+    pub fn demonitor(&mut self, reference: Ref) -> Option<PID> {
+        let i = self.monitors.iter().position(|(r, _)| *r == reference)?;
+        Some(self.monitors.swap_remove(i).1)
+    }
+
+    pub fn take_monitors(&mut self) -> Vec<(Ref, PID)> {
+        std::mem::take(&mut self.monitors)
+    }
+
+    pub fn monitored_by(&mut self, reference: Ref, pid: PID) {
+        self.monitored_by.push((reference, pid));
+    }
+
+    pub fn remove_monitored_by(&mut self, reference: Ref) {
+        self.monitored_by.retain(|(r, _)| *r != reference);
+    }
+
+    pub fn take_monitored_by(&mut self) -> Vec<(Ref, PID)> {
+        std::mem::take(&mut self.monitored_by)
+    }
+
+    pub fn trap_exit(&self) -> bool {
+        self.trap_exit
+    }
+
+    pub fn set_trap_exit(&mut self, trap_exit: bool) -> bool {
+        std::mem::replace(&mut self.trap_exit, trap_exit)
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: Priority) -> Priority {
+        std::mem::replace(&mut self.priority, priority)
+    }
+
+    /// Low priority processes share the normal queue but only get every `RESCHEDULE_LOW`th turn.
+    /// Returns whether this turn should be skipped.
+    pub fn skip_low(&mut self) -> bool {
+        if self.priority != Priority::Low {
+            return false;
+        }
+        self.schedule_count += 1;
+        if self.schedule_count < RESCHEDULE_LOW {
+            true
+        } else {
+            self.schedule_count = 0;
+            false
+        }
+    }
+
+    pub fn set_waiting(&mut self) {
+        assert_eq!(self.status, State::Running);
+        self.status = State::Waiting;
+    }
+
+    /// Makes a waiting process runnable again, returns whether it was waiting
+    pub fn wake(&mut self) -> bool {
+        match self.status {
+            State::Waiting => self.status = State::Runnable,
+            State::Suspended { runnable: false } => {
+                self.status = State::Suspended { runnable: true }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        matches!(self.status, State::Waiting)
+    }
+
+    pub fn has_timer(&self) -> bool {
+        self.timer.is_some()
+    }
+
+    pub fn set_timer(&mut self, deadline: Instant, ip: usize) {
+        self.timer = Some((deadline, ip));
+    }
+
+    pub fn cancel_timer(&mut self) {
+        self.timer = None;
+    }
+
+    /// Wakes the process up at its timeout instruction if it is waiting and its timer has run out
+    pub fn check_timeout(&mut self) {
+        if let Some((deadline, ip)) = self.timer
+            && self.is_waiting()
+            && Instant::now() >= deadline
+        {
+            self.timer = None;
+            self.ip = ip;
+            self.status = State::Runnable;
+        }
+    }
+
     pub fn is_runnable(&self) -> bool {
         matches!(self.status, State::Runnable)
     }
@@ -93,6 +314,21 @@ impl PCB {
         }
     }
 
+    pub fn start_gc(&mut self) {
+        let old_status = std::mem::replace(&mut self.status, State::Free);
+        self.status = State::Garbing {
+            old_status: Box::new(old_status),
+        };
+    }
+
+    pub fn end_gc(&mut self) {
+        if let State::Garbing { old_status } = std::mem::replace(&mut self.status, State::Free) {
+            self.status = *old_status;
+        } else {
+            panic!("end_gc called on process that is not garbage collecting");
+        }
+    }
+
     pub fn set_next(&mut self, next: Arc<Mutex<Process>>) {
         self.next = Some(next);
     }
@@ -102,8 +338,16 @@ impl PCB {
         self.status = State::Running;
     }
 
-    pub fn next(&self) -> Option<&Arc<Mutex<Process>>> {
-        self.next.as_ref()
+    pub fn take_next(&mut self) -> Option<Arc<Mutex<Process>>> {
+        self.next.take()
+    }
+
+    /// Records that the process now runs on `scheduler`. Only the process's own copy of its PID
+    /// is updated, which is fine since PIDs compare equal regardless of scheduler.
+    pub fn migrate(&mut self, scheduler: usize) {
+        if let DataObject::Pid(pid) = &mut self.id {
+            pid.set_scheduler(scheduler);
+        }
     }
 
     pub fn id(&self) -> &DataObject {
@@ -124,3 +368,32 @@ enum State {
     Waiting,
     Garbing { old_status: Box<State> },
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Max,
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "max" => Some(Priority::Max),
+            "high" => Some(Priority::High),
+            "normal" => Some(Priority::Normal),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::Max => "max",
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Table of live processes, indexed by the slot number stored in the low bits of their PIDs. The
+//! high bits hold a serial that is bumped whenever a slot is freed, so a PID of a dead process
+//! never finds the process that reused its slot.
+//!
+//! Lookups, and so sends, never take a lock. Each slot publishes its process through an atomic
+//! pointer and counts the lookups reading it, which `remove` waits out before freeing the entry.
+use std::{
+    fmt::Debug,
+    hint, ptr,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+    },
+};
+
+use crate::{mem::PID, message::InQueue, vm::Process};
+
+/// Bits of the PID number used for the slot, which limits how many processes can be alive at
+/// once like `+P` in ERTS
+pub const SLOT_BITS: usize = 20;
+pub const MAX_PROCESSES: usize = 1 << SLOT_BITS;
+/// Serials wrap around so that PID numbers still fit in a pid term
+const SERIAL_MASK: usize = (1 << 24) - 1;
+/// Slots are allocated this many at a time as the table fills up
+const CHUNK_BITS: usize = 10;
+const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+
+struct Entry {
+    /// Whole PID number, so that lookups with an old serial miss
+    number: usize,
+    proc: Arc<Mutex<Process>>,
+    /// Kept next to the process so that senders don't have to lock it
+    inbox: Arc<InQueue>,
+}
+
+#[derive(Default)]
+struct Slot {
+    entry: AtomicPtr<Entry>,
+    /// Lookups that might be reading `entry`
+    readers: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Alloc {
+    /// Serial of every slot handed out so far
+    serials: Vec<usize>,
+    free: Vec<usize>,
+}
+
+/// Only spawning and exiting take the allocation lock
+pub struct ProcessTable {
+    chunks: Box<[OnceLock<Box<[Slot]>>]>,
+    alloc: Mutex<Alloc>,
+    len: AtomicUsize,
+    /// Most processes that can be alive at once, at most `MAX_PROCESSES`
+    limit: usize,
+}
+
+impl Default for ProcessTable {
+    fn default() -> Self {
+        Self::with_limit(MAX_PROCESSES)
+    }
+}
+
+impl ProcessTable {
+    pub fn with_limit(limit: usize) -> Self {
+        assert!(limit <= MAX_PROCESSES);
+        Self {
+            chunks: (0..limit.div_ceil(CHUNK_SIZE))
+                .map(|_| OnceLock::new())
+                .collect(),
+            alloc: Mutex::default(),
+            len: AtomicUsize::new(0),
+            limit,
+        }
+    }
+
+    fn number(slot: usize, serial: usize) -> usize {
+        (serial << SLOT_BITS) | slot
+    }
+
+    fn slot(&self, slot: usize) -> Option<&Slot> {
+        let chunk = self.chunks.get(slot >> CHUNK_BITS)?.get()?;
+        Some(&chunk[slot & (CHUNK_SIZE - 1)])
+    }
+
+    /// Reserves a slot and stores the process `new` builds from its PID number. Returns `None`
+    /// if the table is full. Only the reservation takes the allocation lock, the process is built
+    /// and published outside of it.
+    pub fn insert(&self, new: impl FnOnce(usize) -> Process) -> Option<Arc<Mutex<Process>>> {
+        let (slot, number) = {
+            let mut alloc = self.alloc.lock().unwrap();
+            let slot = match alloc.free.pop() {
+                Some(slot) => slot,
+                None if alloc.serials.len() < self.limit => {
+                    alloc.serials.push(0);
+                    alloc.serials.len() - 1
+                }
+                None => return None,
+            };
+            (slot, Self::number(slot, alloc.serials[slot]))
+        };
+        // Nobody else writes a reserved slot, and lookups miss it until the entry is stored
+        let proc = new(number);
+        let inbox = proc.inbox().clone();
+        let proc = Arc::new(Mutex::new(proc));
+        let entry = Box::new(Entry {
+            number,
+            proc: proc.clone(),
+            inbox,
+        });
+        self.chunks[slot >> CHUNK_BITS]
+            .get_or_init(|| (0..CHUNK_SIZE).map(|_| Slot::default()).collect())
+            [slot & (CHUNK_SIZE - 1)]
+            .entry
+            .store(Box::into_raw(entry), Ordering::SeqCst);
+        self.len.fetch_add(1, Ordering::SeqCst);
+        Some(proc)
+    }
+
+    fn with_entry<T>(&self, pid: &PID, f: impl FnOnce(&Entry) -> T) -> Option<T> {
+        let number = pid.number();
+        let slot = self.slot(number & (MAX_PROCESSES - 1))?;
+        slot.readers.fetch_add(1, Ordering::SeqCst);
+        let entry = slot.entry.load(Ordering::SeqCst);
+        // SAFETY: `remove` unpublishes an entry and then waits for `readers` to drop to zero
+        // before freeing it, so an entry loaded after registering as a reader stays alive
+        let res = unsafe { entry.as_ref() }
+            .filter(|entry| entry.number == number)
+            .map(f);
+        slot.readers.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    pub fn lookup(&self, pid: &PID) -> Option<Arc<Mutex<Process>>> {
+        self.with_entry(pid, |entry| entry.proc.clone())
+    }
+
+    /// Inbound message queue of `pid` along with the process itself
+    pub fn lookup_inbox(&self, pid: &PID) -> Option<(Arc<InQueue>, Arc<Mutex<Process>>)> {
+        self.with_entry(pid, |entry| (entry.inbox.clone(), entry.proc.clone()))
+    }
+
+    /// Frees the slot of `pid`. Does nothing if the process is already gone.
+    pub fn remove(&self, pid: &PID) {
+        let number = pid.number();
+        let i = number & (MAX_PROCESSES - 1);
+        let Some(slot) = self.slot(i) else {
+            return;
+        };
+        let mut alloc = self.alloc.lock().unwrap();
+        let entry = slot.entry.load(Ordering::SeqCst);
+        // SAFETY: entries are only freed here, under the allocation lock
+        if unsafe { entry.as_ref() }.is_none_or(|entry| entry.number != number) {
+            return;
+        }
+        slot.entry.store(ptr::null_mut(), Ordering::SeqCst);
+        // Lookups hold on for a couple of reference count bumps at most
+        while slot.readers.load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+        alloc.serials[i] = (alloc.serials[i] + 1) & SERIAL_MASK;
+        alloc.free.push(i);
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        drop(alloc);
+        // SAFETY: the entry is unpublished and no lookup that could have seen it is left
+        drop(unsafe { Box::from_raw(entry) });
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ProcessTable {
+    fn drop(&mut self) {
+        for chunk in self.chunks.iter_mut().filter_map(OnceLock::get_mut) {
+            for slot in chunk.iter_mut() {
+                let entry = *slot.entry.get_mut();
+                if !entry.is_null() {
+                    // SAFETY: nothing else can reach the table anymore
+                    drop(unsafe { Box::from_raw(entry) });
+                }
+            }
+        }
+    }
+}
+
+impl Debug for ProcessTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProcessTable({} processes)", self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use crate::{
+        mem::PID,
+        module::Module,
+        vm::{Process, Shared},
+    };
+
+    use super::{ProcessTable, SLOT_BITS};
+
+    fn insert(table: &ProcessTable) -> PID {
+        let (tx, _) = mpsc::channel();
+        let vm = Shared::new(tx);
+        let proc = table
+            .insert(|num| Process::new(PID::new(0, num), Module::new("test", Vec::new()), vm))
+            .unwrap();
+        proc.lock().unwrap().id().expect_pid().clone()
+    }
+
+    #[test]
+    fn insert_lookup_remove() {
+        let table = ProcessTable::default();
+        let a = insert(&table);
+        let b = insert(&table);
+        assert_eq!((a.number(), b.number()), (0, 1));
+        assert_eq!(table.len(), 2);
+        let found = table.lookup(&b).unwrap();
+        assert_eq!(found.lock().unwrap().id().expect_pid(), &b);
+
+        table.remove(&a);
+        assert!(table.lookup(&a).is_none());
+        assert_eq!(table.len(), 1);
+        // Removing twice is harmless
+        table.remove(&a);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn limit() {
+        let table = ProcessTable::with_limit(2);
+        let a = insert(&table);
+        insert(&table);
+        let (tx, _) = mpsc::channel();
+        let vm = Shared::new(tx);
+        let new = |num| Process::new(PID::new(0, num), Module::new("test", Vec::new()), vm);
+        assert!(table.insert(new).is_none());
+        // Freed slots can be used again
+        table.remove(&a);
+        insert(&table);
+    }
+
+    #[test]
+    fn build_outside_lock() {
+        let table = ProcessTable::default();
+        let (tx, _) = mpsc::channel();
+        let vm = Shared::new(tx);
+        // Spawning from inside `new` would deadlock if it ran under the allocation lock
+        let proc = table
+            .insert(|num| {
+                let inner = insert(&table);
+                assert_eq!(inner.number(), 1);
+                Process::new(PID::new(0, num), Module::new("test", Vec::new()), vm)
+            })
+            .unwrap();
+        assert_eq!(proc.lock().unwrap().id().expect_pid().number(), 0);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn lookups_race_removal() {
+        let table = ProcessTable::default();
+        let pid = insert(&table);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        if let Some(proc) = table.lookup(&pid) {
+                            assert_eq!(proc.lock().unwrap().id().expect_pid(), &pid);
+                        }
+                    }
+                });
+            }
+            table.remove(&pid);
+        });
+        assert!(table.lookup(&pid).is_none());
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn stale_pids() {
+        let table = ProcessTable::default();
+        let old = insert(&table);
+        table.remove(&old);
+        let new = insert(&table);
+        // Same slot, different serial
+        assert_eq!(new.number(), 1 << SLOT_BITS);
+        assert_ne!(old, new);
+        assert!(table.lookup(&old).is_none());
+        table.remove(&old);
+        assert!(table.lookup(&new).is_some());
+        assert!(table.lookup(&PID::new(0, 5)).is_none());
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use crate::mem::PID;
+
+/// Names registered with `register/2`. Names are atom indices.
+#[derive(Debug, Default)]
+pub struct Registry {
+    names: HashMap<usize, PID>,
+    pids: HashMap<PID, usize>,
+}
+
+impl Registry {
+    /// Fails if either the name or the process is registered already
+    pub fn register(&mut self, name: usize, pid: PID) -> bool {
+        if self.names.contains_key(&name) || self.pids.contains_key(&pid) {
+            return false;
+        }
+        self.names.insert(name, pid.clone());
+        self.pids.insert(pid, name);
+        true
+    }
+
+    /// Returns whether the name was registered
+    pub fn unregister(&mut self, name: usize) -> bool {
+        match self.names.remove(&name) {
+            Some(pid) => {
+                self.pids.remove(&pid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn whereis(&self, name: usize) -> Option<PID> {
+        self.names.get(&name).cloned()
+    }
+
+    /// Releases the name of `pid`, if it has one
+    pub fn remove_pid(&mut self, pid: &PID) {
+        if let Some(name) = self.pids.remove(pid) {
+            self.names.remove(&name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::PID;
+
+    use super::Registry;
+
+    #[test]
+    fn names() {
+        let mut registry = Registry::default();
+        assert!(registry.register(0, PID::new(0, 1)));
+        assert!(!registry.register(0, PID::new(0, 2)));
+        assert!(!registry.register(1, PID::new(0, 1)));
+        assert_eq!(registry.whereis(0), Some(PID::new(0, 1)));
+        assert_eq!(registry.whereis(1), None);
+
+        registry.remove_pid(&PID::new(0, 1));
+        assert_eq!(registry.whereis(0), None);
+        assert!(registry.register(1, PID::new(0, 1)));
+        assert!(registry.unregister(1));
+        assert!(!registry.unregister(1));
+    }
+}
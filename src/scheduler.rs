@@ -3,72 +3,165 @@ use std::{
     thread,
 };
 
-use crate::vm::Process;
+use crate::{mem, pcb::Priority, vm::Process};
 
 pub enum SchedCmd {
     Kill,
-    Spawn(Arc<Mutex<Process>>),
 }
 
+/// Intrusive linked list of processes through `PCB::next`
+#[derive(Debug, Default)]
+struct ProcQueue {
+    first: Option<Arc<Mutex<Process>>>,
+    last: Option<Arc<Mutex<Process>>>,
+    len: usize,
+}
+
+impl ProcQueue {
+    fn pop(&mut self) -> Option<Arc<Mutex<Process>>> {
+        let old_first = self.first.take();
+        if let Some(ref old_first) = old_first {
+            let next = old_first.lock().unwrap().pcb_mut().take_next();
+            if next.is_none() {
+                self.last = None;
+            }
+            self.first = next;
+            self.len -= 1;
+        }
+        old_first
+    }
+
+    /// Takes the first process that is ready to run, moving the ones in front of it that wait
+    /// for a message or a timeout to the back
+    fn pop_ready(&mut self) -> Option<Arc<Mutex<Process>>> {
+        for _ in 0..self.len {
+            let proc = self.pop()?;
+            if proc.lock().unwrap().is_ready() {
+                return Some(proc);
+            }
+            self.push(proc);
+        }
+        None
+    }
+
+    fn push(&mut self, process: Arc<Mutex<Process>>) {
+        if let Some(old_last) = &self.last {
+            old_last.lock().unwrap().pcb_mut().set_next(process.clone());
+        } else {
+            self.first = Some(process.clone());
+        }
+        self.last = Some(process);
+        self.len += 1;
+    }
+}
+
+/// One queue per priority level. Low priority processes share the normal queue, see
+/// `PCB::skip_low`.
+#[derive(Debug, Default)]
+pub struct RunQueue {
+    max: ProcQueue,
+    high: ProcQueue,
+    normal: ProcQueue,
+}
+
+impl RunQueue {
+    pub fn len(&self) -> usize {
+        self.max.len + self.high.len + self.normal.len
+    }
+
+    /// Next process to run, always taken from the highest priority queue that has one ready.
+    /// Waiting processes stay queued but are passed over, so they can't starve the processes
+    /// behind them.
+    pub fn pop(&mut self) -> Option<Arc<Mutex<Process>>> {
+        if let Some(proc) = self.max.pop_ready().or_else(|| self.high.pop_ready()) {
+            return Some(proc);
+        }
+        loop {
+            let proc = self.normal.pop_ready()?;
+            if proc.lock().unwrap().pcb_mut().skip_low() {
+                self.normal.push(proc);
+            } else {
+                return Some(proc);
+            }
+        }
+    }
+
+    pub fn push(&mut self, process: Arc<Mutex<Process>>) {
+        let priority = process.lock().unwrap().pcb().priority();
+        match priority {
+            Priority::Max => self.max.push(process),
+            Priority::High => self.high.push(process),
+            Priority::Normal | Priority::Low => self.normal.push(process),
+        }
+    }
+}
+
+/// Run queues of all schedulers, indexed by scheduler id
+pub type RunQueues = Arc<Vec<Mutex<RunQueue>>>;
+
 #[derive(Debug)]
 pub struct Scheduler {
-    // TODO: three queues based on priority
-    ready_queue_first: Option<Arc<Mutex<Process>>>,
-    ready_queue_last: Option<Arc<Mutex<Process>>>,
+    queues: RunQueues,
     procs_recvd: usize,
     rx: Receiver<SchedCmd>,
     id: usize,
+    /// Creation of the VM, which references made on this scheduler carry
+    creation: usize,
 }
 
 impl Scheduler {
-    pub fn new(id: usize, rx: Receiver<SchedCmd>) -> Self {
+    pub fn new(id: usize, creation: usize, queues: RunQueues, rx: Receiver<SchedCmd>) -> Self {
         Self {
-            ready_queue_first: None,
-            ready_queue_last: None,
+            queues,
             procs_recvd: 0,
             rx,
             id,
+            creation,
         }
     }
 
+    /// Number of processes this scheduler has stolen from others
+    #[allow(dead_code)]
+    pub fn procs_recvd(&self) -> usize {
+        self.procs_recvd
+    }
+
     fn pop_ready_queue(&mut self) -> Option<Arc<Mutex<Process>>> {
-        let old_first = self.ready_queue_first.clone();
-        if let Some(ref old_first) = old_first {
-            let next = old_first.lock().unwrap().pcb().next().cloned();
-            if next.is_none() {
-                self.ready_queue_last = None;
-            }
-            self.ready_queue_first = next;
-        }
-        old_first
+        self.queues[self.id].lock().unwrap().pop()
     }
 
     fn push_ready_queue(&mut self, process: Arc<Mutex<Process>>) {
-        if let Some(old_last) = &self.ready_queue_last {
-            old_last.lock().unwrap().pcb_mut().set_next(process.clone());
-        } else {
-            self.ready_queue_first = Some(process.clone());
-        }
-        self.ready_queue_last = Some(process);
+        self.queues[self.id].lock().unwrap().push(process);
+    }
+
+    /// Takes a process from the first other scheduler that has some queued, starting with the
+    /// next one so that thieves don't all go for the same victim
+    fn steal(&mut self) -> Option<Arc<Mutex<Process>>> {
+        let n = self.queues.len();
+        let proc = (1..n)
+            .map(|i| (self.id + i) % n)
+            .find_map(|victim| self.queues[victim].try_lock().ok()?.pop())?;
+        proc.lock().unwrap().migrate(self.id);
+        self.procs_recvd += 1;
+        Some(proc)
     }
 
     pub fn run(&mut self) {
+        mem::set_ref_scheduler(self.creation, self.id);
         let mut kill_recvd = false;
         loop {
             if let Ok(msg) = self.rx.try_recv() {
                 match msg {
                     SchedCmd::Kill => kill_recvd = true,
-                    SchedCmd::Spawn(proc) => {
-                        println!("received process");
-                        self.procs_recvd += 1;
-                        self.push_ready_queue(proc);
-                    }
                 }
-            } else if let Some(first) = self.pop_ready_queue() {
+            } else if let Some(first) = self.pop_ready_queue().or_else(|| self.steal()) {
                 let done = {
                     let first = first.clone();
                     let mut first = first.lock().unwrap();
-                    if first.pcb().is_runnable() {
+                    if first.pcb().is_exiting() {
+                        // Killed by an exit signal while it was queued
+                        true
+                    } else if first.is_ready() {
                         println!("running process {:?}", first.id());
                         first.run()
                     } else {
@@ -88,3 +181,160 @@ impl Scheduler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, mpsc};
+
+    use crate::{
+        mem::{self, DataObject, PID},
+        module::Module,
+        parse_str,
+        pcb::Priority,
+        vm::{Process, Shared},
+    };
+
+    use super::{RunQueue, SchedCmd, Scheduler};
+
+    fn process_with_code(num: usize, code: &str) -> Arc<Mutex<Process>> {
+        let (tx, _) = mpsc::channel();
+        Arc::new(Mutex::new(Process::new(
+            PID::new(0, num),
+            Module::new("test", parse_str(code)),
+            Shared::new(tx),
+        )))
+    }
+
+    fn process(num: usize) -> Arc<Mutex<Process>> {
+        let (tx, _) = mpsc::channel();
+        Arc::new(Mutex::new(Process::new(
+            PID::new(0, num),
+            Module::new("test", Vec::new()),
+            Shared::new(tx),
+        )))
+    }
+
+    fn pid(process: &Arc<Mutex<Process>>) -> PID {
+        process.lock().unwrap().id().expect_pid().clone()
+    }
+
+    #[test]
+    fn run_queue() {
+        let mut queue = RunQueue::default();
+        queue.push(process(0));
+        queue.push(process(1));
+        let first = queue.pop().unwrap();
+        assert_eq!(pid(&first), PID::new(0, 0));
+        // Requeueing must not leave a stale link behind
+        queue.push(first);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(pid(&queue.pop().unwrap()), PID::new(0, 1));
+        assert_eq!(pid(&queue.pop().unwrap()), PID::new(0, 0));
+        assert!(queue.pop().is_none());
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn priorities() {
+        let mut queue = RunQueue::default();
+        for (num, priority) in [
+            Priority::Low,
+            Priority::Normal,
+            Priority::High,
+            Priority::Max,
+            Priority::Normal,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let proc = process(num);
+            proc.lock().unwrap().pcb_mut().set_priority(priority);
+            queue.push(proc);
+        }
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|proc| pid(&proc))
+            .collect();
+        assert_eq!(order, [3, 2, 1, 4, 0].map(|num| PID::new(0, num)).to_vec());
+    }
+
+    #[test]
+    fn low_priority_turns() {
+        let mut queue = RunQueue::default();
+        let low = process(0);
+        low.lock().unwrap().pcb_mut().set_priority(Priority::Low);
+        queue.push(low);
+        queue.push(process(1));
+
+        // The normal process gets several turns for every turn of the low priority one
+        let mut order = Vec::new();
+        for _ in 0..10 {
+            let proc = queue.pop().unwrap();
+            order.push(pid(&proc));
+            queue.push(proc);
+        }
+        let low_turns = order.iter().filter(|pid| **pid == PID::new(0, 0)).count();
+        assert_eq!(low_turns, 1);
+    }
+
+    #[test]
+    fn waiting_processes_are_skipped() {
+        let waiting = process_with_code(0, "{label, 1}. {wait, 1}.");
+        {
+            let mut waiting = waiting.lock().unwrap();
+            waiting.pcb_mut().set_priority(Priority::High);
+            assert!(!waiting.run());
+            assert!(waiting.pcb().is_waiting());
+        }
+        let normal = process_with_code(1, "{move, {x, 0}, 1}.");
+        let queues = Arc::new(vec![
+            Mutex::new(RunQueue::default()),
+            Mutex::new(RunQueue::default()),
+        ]);
+        queues[0].lock().unwrap().push(waiting.clone());
+        queues[0].lock().unwrap().push(normal.clone());
+
+        // The scheduler stops once nothing is ready to run, which it would never get to if the
+        // waiting process kept getting picked
+        let (tx, rx) = mpsc::channel();
+        tx.send(SchedCmd::Kill).unwrap();
+        Scheduler::new(0, mem::next_creation(), queues.clone(), rx).run();
+        assert_eq!(
+            normal.lock().unwrap().pcb().exit_reason(),
+            Some(&DataObject::atom("normal"))
+        );
+        assert!(waiting.lock().unwrap().pcb().is_waiting());
+        assert_eq!(queues[0].lock().unwrap().len(), 1);
+
+        // Nor can it be stolen
+        let (_tx, rx) = mpsc::channel();
+        assert!(
+            Scheduler::new(1, mem::next_creation(), queues.clone(), rx)
+                .steal()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn steal() {
+        let queues = Arc::new(vec![
+            Mutex::new(RunQueue::default()),
+            Mutex::new(RunQueue::default()),
+        ]);
+        queues[0].lock().unwrap().push(process(0));
+        queues[0].lock().unwrap().push(process(1));
+
+        let (_tx, rx) = mpsc::channel();
+        let mut thief = Scheduler::new(1, mem::next_creation(), queues.clone(), rx);
+        let stolen = thief.steal().unwrap();
+        let stolen_pid = pid(&stolen);
+        assert_eq!(stolen_pid.scheduler(), 1);
+        // Migrating doesn't change which process the pid refers to
+        assert_eq!(stolen_pid, PID::new(0, 0));
+        assert_eq!(queues[0].lock().unwrap().len(), 1);
+
+        thief.steal().unwrap();
+        assert!(thief.steal().is_none());
+        assert_eq!(thief.procs_recvd(), 2);
+    }
+}
@@ -1,53 +1,106 @@
 use std::{
+    cmp::Ordering,
     sync::{
-        Arc, Mutex, MutexGuard,
+        Arc, Mutex,
         mpsc::{self, Sender},
     },
     thread,
+    time::{Duration, Instant},
 };
 
+use num_bigint::Sign;
+
 use crate::{
     DataObject, Instruction, Reg,
-    mem::{PID, Registers, stack::Stack},
-    message::Mailbox,
-    pcb::PCB,
-    scheduler::{SchedCmd, Scheduler},
+    arith::{self, ArithOp},
+    bif::Bif,
+    instr::{ExtFunc, Src},
+    mem::{
+        self, FLOAT_WORDS, FloatRegisters, NUM_X_REGS, PID, REF_WORDS, Ref, Registers,
+        SUB_BIN_WORDS, atom, copy, gc,
+        heap::{Heap, MIN_HEAP_SIZE},
+        new_float_registers, new_registers,
+        stack::Stack,
+        term::Term,
+    },
+    message::{InQueue, MBuf, Mailbox},
+    module::{CodeServer, MFA, Module},
+    parsing::Source,
+    pcb::{PCB, Priority, Reductions},
+    process_table::{MAX_PROCESSES, ProcessTable},
+    registry::Registry,
+    scheduler::{RunQueue, RunQueues, SchedCmd, Scheduler},
 };
 
 pub enum VMCmd {
+    #[allow(dead_code)]
     Kill,
-    Spawn(Vec<Instruction>),
-    SendToProc(PID, DataObject),
+    /// Queues a process that another process spawned
+    Schedule(Arc<Mutex<Process>>),
+    /// Tells the second process about a link the first one created
+    Link(PID, PID),
+    Unlink(PID, PID),
+    /// Exit signal, coming from a link if `linked` is set and from `exit/2` otherwise
+    Exit {
+        from: PID,
+        to: PID,
+        reason: DataObject,
+        linked: bool,
+    },
+    Monitor {
+        watcher: PID,
+        target: PID,
+        reference: Ref,
+    },
+    Demonitor {
+        target: PID,
+        reference: Ref,
+    },
+    /// A monitored process terminated
+    Down {
+        to: PID,
+        reference: Ref,
+        from: PID,
+        reason: DataObject,
+    },
 }
 
 #[derive(Debug)]
 pub struct VM {
-    registers: Registers,
     schedulers: Vec<Sender<SchedCmd>>,
-    procs: Vec<Arc<Mutex<Process>>>,
+    queues: RunQueues,
+    shared: Arc<Shared>,
 
-    /// Send handle for spawned processes
-    tx: mpsc::Sender<VMCmd>,
+    /// Time slice configuration given to every spawned process
+    reductions: Reductions,
 }
 
 impl VM {
     pub fn new() -> Arc<Mutex<Self>> {
-        let registers = Arc::new(Mutex::new(core::array::from_fn(|_| DataObject::Nil)));
-        let mut schedulers = Vec::with_capacity(thread::available_parallelism().unwrap().get() - 1);
-        for i in 0..schedulers.capacity() {
+        Self::with_reductions(Reductions::default())
+    }
+
+    /// Creates a VM whose processes get time slices of `reductions.budget` reductions
+    pub fn with_reductions(reductions: Reductions) -> Arc<Mutex<Self>> {
+        let n = (thread::available_parallelism().unwrap().get() - 1).max(1);
+        let queues: RunQueues = Arc::new((0..n).map(|_| Mutex::new(RunQueue::default())).collect());
+        let creation = mem::next_creation();
+        let mut schedulers = Vec::with_capacity(n);
+        for i in 0..n {
             let (tx, rx) = mpsc::channel();
             schedulers.push(tx);
+            let queues = queues.clone();
             thread::spawn(move || {
-                Scheduler::new(i, rx).run();
+                Scheduler::new(i, creation, queues, rx).run();
             });
         }
 
         let (tx, rx) = mpsc::channel();
         let vm = Arc::new(Mutex::new(Self {
-            registers: registers.clone(),
             schedulers,
-            procs: Vec::new(),
-            tx,
+            queues,
+            shared: Shared::new(tx),
+            reductions,
         }));
         let vm2 = vm.clone();
         thread::spawn(move || {
@@ -59,34 +112,141 @@ impl VM {
     fn listen_cmd(vm: Arc<Mutex<Self>>, rx: mpsc::Receiver<VMCmd>) {
         while let Ok(cmd) = rx.recv() {
             match cmd {
-                VMCmd::Spawn(instrs) => {
-                    let mut vm = vm.lock().unwrap();
-                    vm.spawn(instrs)
+                VMCmd::Schedule(proc) => {
+                    let vm = vm.lock().unwrap();
+                    vm.schedule(proc);
+                }
+                VMCmd::Link(from, to) => {
+                    let vm = vm.lock().unwrap();
+                    vm.link(from, to);
+                }
+                VMCmd::Unlink(from, to) => {
+                    let vm = vm.lock().unwrap();
+                    if let Some(proc) = vm.find(&to) {
+                        proc.lock().unwrap().pcb_mut().unlink(&from);
+                    }
                 }
-                VMCmd::SendToProc(pid, data_object) => {
+                VMCmd::Exit {
+                    from,
+                    to,
+                    reason,
+                    linked,
+                } => {
                     let vm = vm.lock().unwrap();
-                    vm.procs
-                        .iter()
-                        .find(|p| p.lock().unwrap().id().expect_pid() == &pid)
-                        .unwrap()
-                        .lock()
-                        .unwrap()
-                        .write_to_mailbox(data_object);
+                    if let Some(proc) = vm.find(&to) {
+                        proc.lock().unwrap().exit_signal(from, reason, linked);
+                    }
+                }
+                VMCmd::Monitor {
+                    watcher,
+                    target,
+                    reference,
+                } => {
+                    let vm = vm.lock().unwrap();
+                    let monitored = vm.find(&target).is_some_and(|proc| {
+                        // Checked under the same lock as the insert, otherwise the target could
+                        // finish exiting in between and never see the monitor
+                        let mut proc = proc.lock().unwrap();
+                        let alive = !proc.pcb().is_exiting();
+                        if alive {
+                            proc.pcb_mut().monitored_by(reference, watcher.clone());
+                        }
+                        alive
+                    });
+                    if !monitored && let Some(proc) = vm.find(&watcher) {
+                        let noproc = DataObject::atom("noproc");
+                        proc.lock().unwrap().down(reference, target, noproc);
+                    }
+                }
+                VMCmd::Demonitor { target, reference } => {
+                    let vm = vm.lock().unwrap();
+                    if let Some(proc) = vm.find(&target) {
+                        proc.lock()
+                            .unwrap()
+                            .pcb_mut()
+                            .remove_monitored_by(reference);
+                    }
+                }
+                VMCmd::Down {
+                    to,
+                    reference,
+                    from,
+                    reason,
+                } => {
+                    let vm = vm.lock().unwrap();
+                    if let Some(proc) = vm.find(&to) {
+                        proc.lock().unwrap().down(reference, from, reason);
+                    }
                 }
                 VMCmd::Kill => break,
             }
         }
     }
 
-    pub fn spawn(&mut self, instrs: Vec<Instruction>) {
-        let proc = Arc::new(Mutex::new(Process::new(
-            PID::new(0, self.procs.len()),
-            instrs,
-            self.registers.clone(),
-            self.tx.clone(),
-        )));
-        self.procs.push(proc.clone());
-        self.schedulers[0].send(SchedCmd::Spawn(proc)).unwrap();
+    fn find(&self, pid: &PID) -> Option<Arc<Mutex<Process>>> {
+        self.shared.procs.lookup(pid)
+    }
+
+    /// Adds `from` to the links of `to`. Linking to a process that is already gone sends `from` a
+    /// `noproc` exit signal instead.
+    fn link(&self, from: PID, to: PID) {
+        let from_proc = self.find(&from);
+        let linked = self.find(&to).is_some_and(|proc| {
+            // Checked under the same lock as the insert, otherwise `to` could finish exiting in
+            // between and never signal the link
+            let mut proc = proc.lock().unwrap();
+            if proc.pcb().is_exiting() {
+                return false;
+            }
+            proc.pcb_mut().link(from.clone());
+            // The linking side might have died before the link got here
+            let reason = match &from_proc {
+                Some(from_proc) => {
+                    let from_proc = from_proc.lock().unwrap();
+                    from_proc.pcb().exit_reason().cloned()
+                }
+                None => Some(DataObject::atom("noproc")),
+            };
+            if let Some(reason) = reason {
+                proc.exit_signal(from.clone(), reason, true);
+            }
+            true
+        });
+        if !linked && let Some(from_proc) = from_proc {
+            let noproc = DataObject::atom("noproc");
+            from_proc.lock().unwrap().exit_signal(to, noproc, true);
+        }
+    }
+
+    /// Loads `source` so that other modules can call the functions it exports. Fails if it exports
+    /// a function it doesn't define.
+    pub fn load(&self, source: Source) -> Result<Arc<Module>, String> {
+        self.shared.code.load(source)
+    }
+
+    /// Starts a process at the first instruction of `code`. Returns `None` if there are too many
+    /// processes already.
+    pub fn spawn(&mut self, code: Arc<Module>) -> Option<PID> {
+        let proc = self.shared.procs.insert(|num| {
+            let mut proc = Process::new(PID::new(0, num), code, self.shared.clone());
+            proc.pcb_mut().set_reductions(self.reductions);
+            proc
+        })?;
+        let pid = proc.lock().unwrap().id().expect_pid().clone();
+        self.schedule(proc);
+        Some(pid)
+    }
+
+    /// Queues a new process on the least loaded scheduler, stealing evens things out from there
+    fn schedule(&self, proc: Arc<Mutex<Process>>) {
+        let (scheduler, queue) = self
+            .queues
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, queue)| queue.lock().unwrap().len())
+            .unwrap();
+        proc.lock().unwrap().migrate(scheduler);
+        queue.lock().unwrap().push(proc);
     }
 
     pub fn wait(&self) {
@@ -97,28 +257,74 @@ impl VM {
     }
 }
 
+/// Parts of the VM that processes use directly
+#[derive(Debug)]
+pub struct Shared {
+    /// Send handle for commands to the VM
+    tx: Sender<VMCmd>,
+    procs: ProcessTable,
+    registry: Mutex<Registry>,
+    code: CodeServer,
+}
+
+impl Shared {
+    pub fn new(tx: Sender<VMCmd>) -> Arc<Self> {
+        Self::with_process_limit(tx, MAX_PROCESSES)
+    }
+
+    /// Allows at most `limit` processes to be alive at once
+    pub fn with_process_limit(tx: Sender<VMCmd>, limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            tx,
+            procs: ProcessTable::with_limit(limit),
+            registry: Mutex::new(Registry::default()),
+            code: CodeServer::default(),
+        })
+    }
+
+    /// Puts `msg` in the inbound queue of `pid`, messages to processes that are gone are dropped
+    pub fn send(&self, pid: &PID, msg: MBuf) {
+        if let Some((inbox, proc)) = self.procs.lookup_inbox(pid) {
+            inbox.push(msg);
+            // If the receiver is busy its scheduler notices the message before running it again
+            if let Ok(mut proc) = proc.try_lock() {
+                proc.pcb_mut().wake();
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Process {
+    /// Code the instruction pointer points into
+    code: Arc<Module>,
     stack: Stack,
     registers: Registers,
-    // heap: Heap,
+    fregs: FloatRegisters,
+    /// Set when a float instruction gives an infinite or NaN result, see `fcheckerror`
+    fp_error: bool,
+    heap: Heap,
+    old_heap: Heap,
     message_area: Mailbox,
+    inbox: Arc<InQueue>,
     pcb: PCB,
-    // TODO: this is weird and also doesn't account for the fact that it may be moved to a
-    // different thread
-    tx: Sender<VMCmd>,
+    vm: Arc<Shared>,
 }
 
 impl Process {
-    pub fn new(id: PID, instrs: Vec<Instruction>, registers: Registers, tx: Sender<VMCmd>) -> Self {
+    pub fn new(id: PID, code: Arc<Module>, vm: Arc<Shared>) -> Self {
         Self {
-            stack: Stack::new_from(instrs),
-            registers,
+            code,
+            stack: Stack::new(),
+            registers: new_registers(),
+            fregs: new_float_registers(),
+            fp_error: false,
             message_area: Mailbox::new(),
+            inbox: Arc::new(InQueue::new()),
             pcb: PCB::new(id),
-            // heap: Vec::new(),
-            // message_area: (),
-            tx,
+            heap: Heap::new(MIN_HEAP_SIZE),
+            old_heap: Heap::new(0),
+            vm,
         }
     }
 
@@ -126,120 +332,961 @@ impl Process {
         self.pcb.id()
     }
 
+    pub fn inbox(&self) -> &Arc<InQueue> {
+        &self.inbox
+    }
+
+    /// Moves messages from the inbound queue into the mailbox. The messages stay in their heap
+    /// fragments until the next garbage collection.
+    fn fetch_messages(&mut self) {
+        for mbuf in self.inbox.take_all() {
+            self.message_area.add_mbuf(mbuf);
+        }
+        // A process that only receives never runs out of heap, so like in ERTS the fragments
+        // trigger a collection once they outgrow the heap
+        if self.message_area.fragment_words() > self.heap.size() {
+            self.garbage_collect(0, false, &mut []);
+        }
+    }
+
+    /// Wakes the process if it waits for messages and some have arrived
+    pub fn check_inbox(&mut self) {
+        if self.pcb.is_waiting() && !self.inbox.is_empty() {
+            self.pcb.wake();
+        }
+    }
+
+    /// Whether a scheduler should pick the process up, because it is runnable or was killed
+    /// while it was queued. Wakes it up first if a message arrived or its timeout passed.
+    pub fn is_ready(&mut self) -> bool {
+        self.pcb.check_timeout();
+        self.check_inbox();
+        self.pcb.is_runnable() || self.pcb.is_exiting()
+    }
+
     // pub fn debug_regs(&self) {
-    //     println!("{:#?}", &self.registers.lock().unwrap()[0..5]);
+    //     println!("{:#?}", &self.registers[0..5]);
     // }
 
-    fn get<T, U: FnOnce(Option<DataObject>) -> T>(&self, reg: &Reg, f: U) -> T {
+    fn get(&self, reg: &Reg) -> Option<Term> {
+        match reg {
+            Reg::X(i) => self.registers.get(*i),
+            Reg::I => Some(Term::cp(self.pcb.get_ip())),
+            Reg::fcalls => Some(Term::small(self.pcb.get_fcalls().try_into().unwrap())),
+            Reg::Y(_) | Reg::CP => self.stack.get(reg).ok(),
+            Reg::Htop => Some(Term::small(self.heap.htop().try_into().unwrap())),
+            Reg::FR(_) => panic!("float registers don't hold terms"),
+
+            Reg::E | Reg::FP => todo!(),
+        }
+    }
+
+    fn put(&mut self, reg: &Reg, data: Term) {
         match reg {
-            Reg::X(i) => {
-                if *i < 1024 {
-                    f(Some(
-                        MutexGuard::map(self.registers.lock().unwrap(), |arr| &mut arr[*i]).clone(),
-                    ))
+            Reg::X(i) => self.registers.set(*i, data),
+            Reg::I | Reg::fcalls | Reg::Htop => panic!("we probably don't want to allow this"),
+            Reg::Y(_) | Reg::CP => self.stack.put(reg, data),
+            Reg::FR(_) => panic!("float registers don't hold terms"),
+
+            Reg::E | Reg::FP => todo!(),
+        }
+    }
+
+    /// Makes sure `need` words can be allocated on the heap
+    fn test_heap(&mut self, need: usize) {
+        self.test_heap_live(need, &mut []);
+    }
+
+    /// Like `test_heap`, for terms that aren't in registers. The terms in `live` are kept alive
+    /// and updated if they move.
+    fn test_heap_live(&mut self, need: usize, live: &mut [Term]) {
+        if !self.heap.test_heap(need) {
+            self.garbage_collect(need, false, live);
+        }
+    }
+
+    fn garbage_collect(&mut self, need: usize, major: bool, live: &mut [Term]) {
+        self.pcb.start_gc();
+        self.pcb.bump_reductions(self.pcb.reductions().gc);
+        let (msgs, fragments) = self.message_area.gc_parts();
+        gc::collect(
+            &mut self.heap,
+            &mut self.old_heap,
+            fragments,
+            &mut [
+                self.registers.written_mut(),
+                self.stack.registers_mut(),
+                msgs,
+                live,
+            ],
+            need,
+            major,
+        );
+        self.pcb.end_gc();
+    }
+
+    /// Builds a literal on the heap. Callers must have made room with `test_heap` first.
+    fn src(&mut self, src: &Src) -> Term {
+        match src {
+            Src::Reg(reg) => self.get(reg).unwrap(),
+            Src::Lit(data) => self.heap.build(data),
+        }
+    }
+
+    /// Builds `data` on the heap, collecting garbage first if there isn't room
+    fn build(&mut self, data: &DataObject) -> Term {
+        self.test_heap(data.size());
+        self.heap.build(data)
+    }
+
+    fn call_bif(&mut self, bif: Bif, args: &[Term]) -> Result<Term, DataObject> {
+        let badarg = || DataObject::atom("badarg");
+        match bif {
+            Bif::Arith(op) => {
+                let [a, b] = args else { unreachable!() };
+                let res = arith::eval(op, *a, *b)?;
+                Ok(self.build(&res))
+            }
+            Bif::Unary(op) => {
+                let [a] = args else { unreachable!() };
+                let res = arith::eval_unary(op, *a)?;
+                Ok(self.build(&res))
+            }
+            Bif::GarbageCollect => {
+                self.garbage_collect(0, true, &mut []);
+                Ok(Term::intern("true"))
+            }
+            Bif::Element => {
+                let [index, tuple] = args else { unreachable!() };
+                if !index.is_small() || !tuple.is_tuple() {
+                    return Err(badarg());
+                }
+                match usize::try_from(index.small_val()) {
+                    Ok(i) if (1..=tuple.tuple_arity()).contains(&i) => Ok(tuple.element(i)),
+                    _ => Err(badarg()),
+                }
+            }
+            Bif::TupleSize => {
+                let [tuple] = args else { unreachable!() };
+                if tuple.is_tuple() {
+                    Ok(Term::small(tuple.tuple_arity() as i64))
+                } else {
+                    Err(badarg())
+                }
+            }
+            Bif::ProcessFlag => {
+                let [flag, value] = args else { unreachable!() };
+                if !value.is_atom() {
+                    return Err(badarg());
+                }
+                if *flag == Term::intern("priority") {
+                    let priority =
+                        Priority::from_name(&atom::name(value.atom_val())).ok_or_else(badarg)?;
+                    let old = self.pcb.set_priority(priority);
+                    Ok(Term::intern(old.name()))
+                } else if *flag == Term::intern("trap_exit") {
+                    let trap_exit = match atom::name(value.atom_val()).as_str() {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(badarg()),
+                    };
+                    let old = self.pcb.set_trap_exit(trap_exit);
+                    Ok(Term::intern(if old { "true" } else { "false" }))
+                } else {
+                    Err(badarg())
+                }
+            }
+            Bif::Link => {
+                let [pid] = args else { unreachable!() };
+                if !pid.is_pid() {
+                    return Err(badarg());
+                }
+                let pid = pid.pid_val();
+                if pid != *self.id().expect_pid() {
+                    self.pcb.link(pid.clone());
+                    let me = self.id().expect_pid().clone();
+                    self.vm.tx.send(VMCmd::Link(me, pid)).unwrap();
+                }
+                Ok(Term::intern("true"))
+            }
+            Bif::Unlink => {
+                let [pid] = args else { unreachable!() };
+                if !pid.is_pid() {
+                    return Err(badarg());
+                }
+                let pid = pid.pid_val();
+                if self.pcb.unlink(&pid) {
+                    let me = self.id().expect_pid().clone();
+                    self.vm.tx.send(VMCmd::Unlink(me, pid)).unwrap();
+                }
+                Ok(Term::intern("true"))
+            }
+            Bif::Exit => {
+                let [pid, reason] = args else { unreachable!() };
+                if !pid.is_pid() {
+                    return Err(badarg());
+                }
+                self.vm
+                    .tx
+                    .send(VMCmd::Exit {
+                        from: self.id().expect_pid().clone(),
+                        to: pid.pid_val(),
+                        reason: DataObject::from(*reason),
+                        linked: false,
+                    })
+                    .unwrap();
+                Ok(Term::intern("true"))
+            }
+            Bif::Monitor => {
+                let [kind, pid] = args else { unreachable!() };
+                if *kind != Term::intern("process") || !pid.is_pid() {
+                    return Err(badarg());
+                }
+                let reference = Ref::new();
+                self.pcb.monitor(reference, pid.pid_val());
+                self.vm
+                    .tx
+                    .send(VMCmd::Monitor {
+                        watcher: self.id().expect_pid().clone(),
+                        target: pid.pid_val(),
+                        reference,
+                    })
+                    .unwrap();
+                self.test_heap(1 + REF_WORDS);
+                Ok(self.heap.reference(reference))
+            }
+            Bif::Register => {
+                let [name, pid] = args else { unreachable!() };
+                if !name.is_atom() || *name == Term::intern("undefined") || !pid.is_pid() {
+                    return Err(badarg());
+                }
+                let pid = pid.pid_val();
+                let mut registry = self.vm.registry.lock().unwrap();
+                // `terminate` takes a process out of the table before it frees its names, so a
+                // process found here will free this one too. Processes that are running, like
+                // this one, can't be locked and aren't exiting.
+                let alive = self.vm.procs.lookup(&pid).is_some_and(|proc| {
+                    proc.try_lock()
+                        .map_or(true, |proc| !proc.pcb().is_exiting())
+                });
+                if alive && registry.register(name.atom_val(), pid) {
+                    Ok(Term::intern("true"))
+                } else {
+                    Err(badarg())
+                }
+            }
+            Bif::Unregister => {
+                let [name] = args else { unreachable!() };
+                if name.is_atom() && self.vm.registry.lock().unwrap().unregister(name.atom_val()) {
+                    Ok(Term::intern("true"))
+                } else {
+                    Err(badarg())
+                }
+            }
+            Bif::Whereis => {
+                let [name] = args else { unreachable!() };
+                if !name.is_atom() {
+                    return Err(badarg());
+                }
+                match self.vm.registry.lock().unwrap().whereis(name.atom_val()) {
+                    Some(pid) => Ok(Term::pid(&pid)),
+                    None => Ok(Term::intern("undefined")),
+                }
+            }
+            Bif::Spawn | Bif::SpawnLink => {
+                let [module, function, list] = args else {
+                    unreachable!()
+                };
+                let mut args = Vec::new();
+                let mut list = *list;
+                while list.is_list() {
+                    args.push(list.hd());
+                    list = list.tl();
+                }
+                if !module.is_atom() || !function.is_atom() || !list.is_nil() {
+                    return Err(badarg());
+                }
+                let target = self.vm.code.lookup(&MFA {
+                    module: atom::name(module.atom_val()),
+                    function: atom::name(function.atom_val()),
+                    arity: args.len(),
+                });
+                self.spawn(target, &args, bif == Bif::SpawnLink)
+            }
+            Bif::SpawnFun | Bif::SpawnLinkFun => {
+                let [fun] = args else { unreachable!() };
+                if !fun.is_fun() || fun.fun_arity() != 0 {
+                    return Err(badarg());
+                }
+                let module = atom::name(fun.fun_module().atom_val());
+                let code = if module == self.code.name() {
+                    Some(self.code.clone())
                 } else {
-                    f(None)
+                    self.vm.code.get(&module)
+                };
+                let target = code.map(|code| (code, fun.fun_entry()));
+                self.spawn(target, &fun.fun_env(), bif == Bif::SpawnLinkFun)
+            }
+            Bif::AtomToList => {
+                let [atom] = args else { unreachable!() };
+                if !atom.is_atom() {
+                    return Err(badarg());
+                }
+                let chars = atom::name(atom.atom_val())
+                    .chars()
+                    .map(|c| DataObject::Small(c as i64))
+                    .collect();
+                Ok(self.build(&DataObject::list(chars, DataObject::Nil)))
+            }
+            Bif::ListToAtom => {
+                let [list] = args else { unreachable!() };
+                let name = char_list(*list).ok_or_else(badarg)?;
+                new_atom(&name)
+            }
+            Bif::ListToExistingAtom => {
+                let [list] = args else { unreachable!() };
+                let name = char_list(*list).ok_or_else(badarg)?;
+                atom::existing(&name).map(Term::atom).ok_or_else(badarg)
+            }
+            Bif::BinaryToAtom => {
+                let [bin, encoding] = args else {
+                    unreachable!()
+                };
+                if !bin.is_binary() {
+                    return Err(badarg());
+                }
+                let name = if *encoding == Term::intern("latin1") {
+                    bin.with_bytes(|bytes| bytes.iter().map(|&b| char::from(b)).collect())
+                } else if *encoding == Term::intern("utf8") || *encoding == Term::intern("unicode")
+                {
+                    bin.with_bytes(|bytes| String::from_utf8(bytes.to_vec()))
+                        .map_err(|_| badarg())?
+                } else {
+                    return Err(badarg());
+                };
+                new_atom(&name)
+            }
+            Bif::ByteSize | Bif::BitSize => {
+                let [bin] = args else { unreachable!() };
+                if !bin.is_binary() {
+                    return Err(badarg());
+                }
+                let bytes = bin.byte_size() as i64;
+                Ok(Term::small(if bif == Bif::BitSize {
+                    8 * bytes
+                } else {
+                    bytes
+                }))
+            }
+            Bif::BinaryPart => {
+                let [bin, start, len] = args else {
+                    unreachable!()
+                };
+                if !bin.is_binary() || !start.is_small() || !len.is_small() {
+                    return Err(badarg());
+                }
+                // A negative length counts back from `start`
+                let (start, len) = (start.small_val(), len.small_val());
+                let (start, end) = if len < 0 {
+                    (start + len, start)
+                } else {
+                    (start, start + len)
+                };
+                if start < 0 || end > bin.byte_size() as i64 {
+                    return Err(badarg());
+                }
+                let mut live = [*bin];
+                self.test_heap_live(1 + SUB_BIN_WORDS, &mut live);
+                let [bin] = live;
+                Ok(self
+                    .heap
+                    .sub_binary(bin, start as usize, (end - start) as usize))
+            }
+            Bif::BinaryToList => {
+                let [bin] = args else { unreachable!() };
+                if !bin.is_binary() {
+                    return Err(badarg());
+                }
+                let bytes = bin.with_bytes(|bytes| {
+                    bytes.iter().map(|&b| DataObject::Small(b.into())).collect()
+                });
+                Ok(self.build(&DataObject::list(bytes, DataObject::Nil)))
+            }
+            Bif::ListToBinary => {
+                let [list] = args else { unreachable!() };
+                if !list.is_list() && !list.is_nil() {
+                    return Err(badarg());
+                }
+                let bytes = iolist_bytes(*list).ok_or_else(badarg)?;
+                Ok(self.build(&DataObject::Binary(Arc::new(bytes))))
+            }
+            Bif::MakeRef => {
+                self.test_heap(1 + REF_WORDS);
+                Ok(self.heap.reference(Ref::new()))
+            }
+            Bif::Demonitor => {
+                let [reference] = args else { unreachable!() };
+                if !reference.is_ref() {
+                    return Err(badarg());
+                }
+                self.demonitor(reference.ref_val());
+                Ok(Term::intern("true"))
+            }
+            Bif::DemonitorOpts => {
+                let [reference, opts] = args else {
+                    unreachable!()
+                };
+                if !reference.is_ref() {
+                    return Err(badarg());
+                }
+                let (mut flush, mut info) = (false, false);
+                let mut opts = *opts;
+                while opts.is_list() {
+                    match opts.hd() {
+                        opt if opt == Term::intern("flush") => flush = true,
+                        opt if opt == Term::intern("info") => info = true,
+                        _ => return Err(badarg()),
+                    }
+                    opts = opts.tl();
                 }
+                if !opts.is_nil() {
+                    return Err(badarg());
+                }
+
+                let reference = reference.ref_val();
+                let active = self.demonitor(reference);
+                if flush {
+                    self.fetch_messages();
+                    let down = Term::intern("DOWN");
+                    self.message_area.remove_matching(|msg| {
+                        msg.is_tuple()
+                            && msg.tuple_arity() == 5
+                            && msg.element(1) == down
+                            && msg.element(2).is_ref()
+                            && msg.element(2).ref_val() == reference
+                    });
+                }
+                Ok(Term::intern(if !info || active { "true" } else { "false" }))
             }
-            Reg::I => f(Some(DataObject::IC(self.pcb.get_ip()))),
-            Reg::fcalls => f(Some(DataObject::Small(
-                self.pcb.get_fcalls().try_into().unwrap(),
-            ))),
-            Reg::Y(_) | Reg::CP => f(self.stack.get(reg).ok()),
+        }
+    }
 
-            Reg::Htop | Reg::E | Reg::FP => todo!(),
+    /// Module and entry point of the function a `call_ext` instruction calls, if it is loaded
+    fn ext_target(&self, func: &ExtFunc) -> Option<(Arc<Module>, usize)> {
+        match func {
+            ExtFunc::Import(i) => self.code.import(*i).target(),
+            ExtFunc::Name(mfa) => self.vm.code.lookup(mfa),
         }
     }
 
-    fn put(&mut self, reg: &Reg, data: DataObject) {
-        match reg {
-            Reg::X(i) => {
-                if *i < 1024 {
-                    let mut registers = self.registers.lock().unwrap();
-                    registers[*i] = data;
-                }
+    /// Starts a process at `target` with `args` in its X registers and returns its PID. Like in
+    /// Erlang, spawning a function that doesn't exist works but the new process exits with
+    /// `undef` right away.
+    fn spawn(
+        &mut self,
+        target: Option<(Arc<Module>, usize)>,
+        args: &[Term],
+        link: bool,
+    ) -> Result<Term, DataObject> {
+        if args.len() > NUM_X_REGS {
+            return Err(DataObject::atom("badarg"));
+        }
+        let me = self.id().expect_pid().clone();
+        let scheduler = me.scheduler();
+        let need: usize = args.iter().map(|arg| copy::size(*arg)).sum();
+        let code = match &target {
+            Some((module, _)) => module.clone(),
+            None => self.code.clone(),
+        };
+        let reductions = *self.pcb.reductions();
+        let Some(proc) = self.vm.procs.insert(|num| {
+            let mut proc = Process::new(PID::new(scheduler, num), code, self.vm.clone());
+            proc.pcb.set_reductions(reductions);
+            if need > proc.heap.size() {
+                proc.heap = Heap::new(need);
             }
-            Reg::I | Reg::fcalls => panic!("we probably don't want to allow this"),
-            Reg::Y(_) | Reg::CP => self.stack.put(reg, data),
+            for (i, arg) in args.iter().enumerate() {
+                let arg = copy::copy(*arg, &mut proc.heap);
+                proc.registers.set(i, arg);
+            }
+            if let Some((_, entry)) = target {
+                proc.pcb.set_ip(entry);
+            }
+            // Both sides of the link are set up before anyone else knows about the process
+            if link {
+                proc.pcb.link(me.clone());
+            }
+            proc
+        }) else {
+            return Err(DataObject::atom("system_limit"));
+        };
+        let pid = proc.lock().unwrap().id().expect_pid().clone();
+        if link {
+            self.pcb.link(pid.clone());
+        }
+        if target.is_some() {
+            self.vm.tx.send(VMCmd::Schedule(proc)).unwrap();
+        } else {
+            let mut proc = proc.lock().unwrap();
+            proc.exit(DataObject::atom("undef"));
+            proc.terminate();
+        }
+        Ok(Term::pid(&pid))
+    }
 
-            Reg::Htop | Reg::E | Reg::FP => todo!(),
+    /// Turns off the monitor `reference`. Returns whether it was still active.
+    fn demonitor(&mut self, reference: Ref) -> bool {
+        match self.pcb.demonitor(reference) {
+            Some(target) => {
+                self.vm
+                    .tx
+                    .send(VMCmd::Demonitor { target, reference })
+                    .unwrap();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Delivers the `DOWN` message for `reference` unless the monitor was turned off
+    pub fn down(&mut self, reference: Ref, from: PID, reason: DataObject) {
+        if self.pcb.is_exiting() || self.pcb.demonitor(reference).is_none() {
+            return;
         }
+        self.write_to_mailbox(DataObject::Tuple(vec![
+            DataObject::atom("DOWN"),
+            DataObject::Refer(reference),
+            DataObject::atom("process"),
+            DataObject::Pid(from),
+            reason,
+        ]));
     }
 
-    fn comparison(&mut self, arg0: &Reg, arg1: &Reg, offset: usize, op: impl Fn(u32, u32) -> bool) {
-        let a = self.get(arg0, |i| i.unwrap().expect_int());
-        let b = self.get(arg1, |i| i.unwrap().expect_int());
-        if op(a, b) {
+    /// Terminates the process with `reason`
+    fn exit(&mut self, reason: DataObject) {
+        self.pcb.exit(reason);
+    }
+
+    fn comparison(&mut self, arg0: &Reg, arg1: &Reg, offset: usize, op: impl Fn(Ordering) -> bool) {
+        let a = self.get(arg0).unwrap();
+        let b = self.get(arg1).unwrap();
+        if op(a.compare(b)) {
             self.pcb.set_ip(offset);
         }
     }
 
-    fn type_test(&mut self, arg: &Reg, offset: usize, test: impl Fn(DataObject) -> bool) {
-        if self.get(arg, |a| test(a.unwrap())) {
+    /// Puts the result of a float instruction in float register `dest`
+    fn freg(&self, i: usize) -> f64 {
+        self.fregs.get(i).expect("float register out of range")
+    }
+
+    fn float_result(&mut self, dest: usize, res: f64) {
+        if !res.is_finite() {
+            self.fp_error = true;
+        }
+        self.fregs.set(dest, res);
+    }
+
+    fn type_test(&mut self, arg: &Reg, offset: usize, test: impl Fn(Term) -> bool) {
+        if test(self.get(arg).unwrap()) {
             self.pcb.set_ip(offset);
         }
     }
 
+    /// Sends exit signals to all linked processes. The process must not run again afterwards.
+    fn terminate(&mut self) {
+        let normal = DataObject::atom("normal");
+        let reason = self.pcb.exit_reason().cloned().unwrap_or(normal);
+        if !self.pcb.is_exiting() {
+            self.pcb.exit(reason.clone());
+        }
+        let me = self.id().expect_pid().clone();
+        // In this order so that `register/2` never names a process after its names are freed
+        self.vm.procs.remove(&me);
+        self.vm.registry.lock().unwrap().remove_pid(&me);
+        for (reference, target) in self.pcb.take_monitors() {
+            self.vm
+                .tx
+                .send(VMCmd::Demonitor { target, reference })
+                .unwrap();
+        }
+        for (reference, watcher) in self.pcb.take_monitored_by() {
+            self.vm
+                .tx
+                .send(VMCmd::Down {
+                    to: watcher,
+                    reference,
+                    from: me.clone(),
+                    reason: reason.clone(),
+                })
+                .unwrap();
+        }
+        for pid in self.pcb.take_links() {
+            self.vm
+                .tx
+                .send(VMCmd::Exit {
+                    from: me.clone(),
+                    to: pid,
+                    reason: reason.clone(),
+                    linked: true,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Handles an exit signal from `from`. Signals with reason `kill` from `exit/2` always kill
+    /// the process, otherwise they become messages if the process traps exits and are ignored if
+    /// the reason is `normal`.
+    pub fn exit_signal(&mut self, from: PID, reason: DataObject, linked: bool) {
+        if self.pcb.is_exiting() || (linked && !self.pcb.unlink(&from)) {
+            return;
+        }
+        let kill = DataObject::atom("kill");
+        if !linked && reason == kill {
+            self.exit(DataObject::atom("killed"));
+            self.terminate();
+        } else if self.pcb.trap_exit() {
+            self.write_to_mailbox(DataObject::Tuple(vec![
+                DataObject::atom("EXIT"),
+                DataObject::Pid(from),
+                reason,
+            ]));
+        } else if reason != DataObject::atom("normal") {
+            self.exit(reason);
+            self.terminate();
+        }
+    }
+
     /// returns true if process has finished
     pub fn run(&mut self) -> bool {
+        let done = self.run_slice();
+        if done {
+            self.terminate();
+        }
+        done
+    }
+
+    fn run_slice(&mut self) -> bool {
         self.pcb.set_running();
-        while self.pcb.get_ip() < self.stack.instrs().len() {
-            let instr = self.stack.instrs()[self.pcb.get_ip()].clone();
+        let mut code = self.code.clone();
+        while let Some(instr) = code.instrs().get(self.pcb.get_ip()) {
             self.pcb.inc_ip(1);
+            let cost = self.pcb.reductions().cost(instr);
             // println!("{instr:?}");
             match instr {
                 Instruction::Move { dest, src } => {
-                    self.put(&dest, src);
+                    let term = self.build(src);
+                    self.put(dest, term);
                 }
                 Instruction::Add { arg0, arg1, ret } => {
-                    self.put(
-                        &ret,
-                        DataObject::Small(
-                            self.get(&arg0, |i| i.unwrap().expect_int())
-                                + self.get(&arg1, |i| i.unwrap().expect_int()),
-                        ),
-                    );
+                    let (a, b) = (self.get(arg0).unwrap(), self.get(arg1).unwrap());
+                    match arith::eval(ArithOp::Add, a, b) {
+                        Ok(res) => {
+                            let res = self.build(&res);
+                            self.put(ret, res);
+                        }
+                        Err(reason) => {
+                            self.exit(reason);
+                            return true;
+                        }
+                    }
+                }
+                Instruction::Allocate { stack_need } => self.stack.allocate(*stack_need),
+                Instruction::TestHeap { need } => self.test_heap(*need),
+                Instruction::Bif { bif, args, ret } => {
+                    let args: Vec<_> = args.iter().map(|arg| self.get(arg).unwrap()).collect();
+                    match self.call_bif(*bif, &args) {
+                        Ok(res) => self.put(ret, res),
+                        Err(reason) => {
+                            self.exit(reason);
+                            return true;
+                        }
+                    }
+                }
+                Instruction::GcBif {
+                    bif,
+                    fail,
+                    args,
+                    ret,
+                } => {
+                    self.test_heap(args.iter().map(Src::size).sum());
+                    let args: Vec<_> = args.iter().map(|arg| self.src(arg)).collect();
+                    match (self.call_bif(*bif, &args), fail) {
+                        (Ok(res), _) => self.put(ret, res),
+                        (Err(_), Some(fail)) => self.pcb.set_ip(*fail),
+                        (Err(reason), None) => {
+                            self.exit(reason);
+                            return true;
+                        }
+                    }
+                }
+                Instruction::PutTuple2 { dest, elems } => {
+                    self.test_heap(1 + elems.len() + elems.iter().map(Src::size).sum::<usize>());
+                    let elems: Vec<_> = elems.iter().map(|elem| self.src(elem)).collect();
+                    let tuple = self.heap.tuple(&elems);
+                    self.put(dest, tuple);
+                }
+                Instruction::GetTupleElement { src, index, dest } => {
+                    let tuple = self.get(src).unwrap().expect_tuple();
+                    self.put(dest, tuple.element(*index + 1));
+                }
+                Instruction::SetTupleElement {
+                    elem,
+                    tuple: reg,
+                    index,
+                } => {
+                    // The tuple has to be on the young heap, since minor collections don't scan
+                    // the old heap for pointers to young terms. A collection here may have
+                    // promoted it, in which case it is copied back since nothing else refers to
+                    // a tuple that was just built.
+                    let tuple = self.get(reg).unwrap().expect_tuple();
+                    let mut live = [tuple];
+                    self.test_heap_live(elem.size() + 1 + tuple.tuple_arity(), &mut live);
+                    let [mut tuple] = live;
+                    if !self.heap.contains(tuple.ptr()) {
+                        let elems: Vec<_> = (1..=tuple.tuple_arity())
+                            .map(|i| tuple.element(i))
+                            .collect();
+                        tuple = self.heap.tuple(&elems);
+                        self.put(reg, tuple);
+                    }
+                    let elem = self.src(elem);
+                    tuple.set_element(*index + 1, elem);
+                }
+                Instruction::PutList { hd, tl, dest } => {
+                    self.test_heap(2 + hd.size() + tl.size());
+                    let hd = self.src(hd);
+                    let tl = self.src(tl);
+                    let list = self.heap.cons(hd, tl);
+                    self.put(dest, list);
+                }
+                Instruction::GetList { src, hd, tl } => {
+                    let list = self.get(src).unwrap().expect_list();
+                    self.put(hd, list.hd());
+                    self.put(tl, list.tl());
+                }
+                Instruction::GetHd { src, dest } => {
+                    let list = self.get(src).unwrap().expect_list();
+                    self.put(dest, list.hd());
+                }
+                Instruction::GetTl { src, dest } => {
+                    let list = self.get(src).unwrap().expect_list();
+                    self.put(dest, list.tl());
                 }
-                Instruction::Allocate { stack_need } => self.stack.allocate(stack_need),
                 Instruction::IsLt { lbl, arg0, arg1 } => {
-                    self.comparison(&arg0, &arg1, lbl, |a, b| a < b)
+                    self.comparison(arg0, arg1, *lbl, Ordering::is_lt)
                 }
                 Instruction::IsGe { lbl, arg0, arg1 } => {
-                    self.comparison(&arg0, &arg1, lbl, |a, b| a >= b)
+                    self.comparison(arg0, arg1, *lbl, Ordering::is_ge)
                 }
                 Instruction::IsEq { lbl, arg0, arg1 } => {
-                    self.comparison(&arg0, &arg1, lbl, |a, b| a == b)
+                    self.comparison(arg0, arg1, *lbl, Ordering::is_eq)
                 }
                 Instruction::IsNe { lbl, arg0, arg1 } => {
-                    self.comparison(&arg0, &arg1, lbl, |a, b| a != b)
+                    self.comparison(arg0, arg1, *lbl, Ordering::is_ne)
                 }
                 Instruction::IsInteger { lbl, arg } => {
-                    self.type_test(&arg, lbl, |a| matches!(a, DataObject::Small(_)))
+                    self.type_test(arg, *lbl, |a| a.is_integer())
+                }
+                Instruction::IsFloat { lbl, arg } => self.type_test(arg, *lbl, |a| a.is_float()),
+                Instruction::IsReference { lbl, arg } => self.type_test(arg, *lbl, |a| a.is_ref()),
+                Instruction::IsBinary { lbl, arg } | Instruction::IsBitstring { lbl, arg } => {
+                    self.type_test(arg, *lbl, |a| a.is_binary())
+                }
+                Instruction::IsTuple { lbl, arg } => self.type_test(arg, *lbl, |a| a.is_tuple()),
+                Instruction::TestArity { lbl, arg, arity } => {
+                    self.type_test(arg, *lbl, |a| a.is_tuple() && a.tuple_arity() == *arity)
                 }
+                Instruction::IsList { lbl, arg } => {
+                    self.type_test(arg, *lbl, |a| a.is_list() || a.is_nil())
+                }
+                Instruction::IsNonemptyList { lbl, arg } => {
+                    self.type_test(arg, *lbl, |a| a.is_list())
+                }
+                Instruction::IsNil { lbl, arg } => self.type_test(arg, *lbl, |a| a.is_nil()),
                 Instruction::Ret => {
+                    if let Some(code) = self.stack.return_code() {
+                        self.code = code;
+                    }
                     self.pcb.set_ip(self.stack.cp().unwrap());
                     if self.stack.ret() {
                         return true;
                     }
                 }
                 Instruction::Call { ip } => {
-                    self.stack.allocate_call(ip);
+                    self.stack.allocate_call(*ip);
                     self.pcb.set_ip(self.pcb.get_ip());
-                    if self.pcb.dec_fcalls() {
-                        return false;
+                }
+                Instruction::CallExt { func }
+                | Instruction::CallExtOnly { func }
+                | Instruction::CallExtLast { func, .. } => {
+                    let Some((module, entry)) = self.ext_target(func) else {
+                        self.exit(DataObject::atom("undef"));
+                        return true;
+                    };
+                    match instr {
+                        Instruction::CallExt { .. } => self
+                            .stack
+                            .allocate_call_ext(self.pcb.get_ip(), self.code.clone()),
+                        Instruction::CallExtLast { dealloc, .. } => self.stack.deallocate(*dealloc),
+                        _ => {}
+                    }
+                    self.code = module;
+                    self.pcb.set_ip(entry);
+                }
+                Instruction::FMove { src, dest } => match (src, dest) {
+                    (Src::Reg(Reg::FR(i)), dest) => {
+                        // Boxing the result of a failed operation before `fcheckerror` would
+                        // let NaN or infinity escape into a term
+                        if !self.freg(*i).is_finite() {
+                            self.exit(DataObject::atom("badarith"));
+                            return true;
+                        }
+                        self.test_heap(1 + FLOAT_WORDS);
+                        let float = self.heap.float(self.freg(*i));
+                        self.put(dest, float);
+                    }
+                    (src, Reg::FR(i)) => {
+                        self.test_heap(src.size());
+                        let float = self.src(src).expect_float();
+                        self.fregs.set(*i, float);
+                    }
+                    _ => unreachable!("checked by the parser"),
+                },
+                Instruction::FConv { src, dest } => {
+                    self.test_heap(src.size());
+                    let Some(f) = arith::to_float(self.src(src)) else {
+                        self.exit(DataObject::atom("badarith"));
+                        return true;
+                    };
+                    self.fregs.set(*dest, f);
+                }
+                Instruction::FAdd { arg0, arg1, dest } => {
+                    self.float_result(*dest, self.freg(*arg0) + self.freg(*arg1))
+                }
+                Instruction::FSub { arg0, arg1, dest } => {
+                    self.float_result(*dest, self.freg(*arg0) - self.freg(*arg1))
+                }
+                Instruction::FMul { arg0, arg1, dest } => {
+                    self.float_result(*dest, self.freg(*arg0) * self.freg(*arg1))
+                }
+                Instruction::FDiv { arg0, arg1, dest } => {
+                    self.float_result(*dest, self.freg(*arg0) / self.freg(*arg1))
+                }
+                Instruction::FNegate { arg, dest } => self.float_result(*dest, -self.freg(*arg)),
+                Instruction::FClearError => self.fp_error = false,
+                Instruction::FCheckError => {
+                    if self.fp_error {
+                        self.fp_error = false;
+                        self.exit(DataObject::atom("badarith"));
+                        return true;
                     }
                 }
-                Instruction::Jmp { lbl } => self.pcb.set_ip(lbl),
-                Instruction::Spawn { instrs } => {
-                    self.tx.send(VMCmd::Spawn(instrs)).unwrap();
+                Instruction::Jmp { lbl } => self.pcb.set_ip(*lbl),
+                Instruction::FuncInfo { .. } => {
+                    self.exit(DataObject::atom("function_clause"));
+                    return true;
+                }
+                Instruction::MakeFun {
+                    lbl,
+                    arity,
+                    num_free,
+                } => {
+                    self.test_heap(4 + num_free);
+                    let module = Term::intern(self.code.name());
+                    let fun = self
+                        .heap
+                        .fun(module, *lbl, *arity, self.registers.first(*num_free));
+                    self.put(&Reg::X(0), fun);
                 }
                 Instruction::Send => {
-                    let pid = self.get(&Reg::X(0), |pid| pid.unwrap().expect_pid().clone());
-                    self.get(&Reg::X(1), |data| {
-                        self.tx.send(VMCmd::SendToProc(pid, data.unwrap())).unwrap();
-                    });
+                    let dest = self.get(&Reg::X(0)).unwrap();
+                    let pid = if dest.is_pid() {
+                        Some(dest.pid_val())
+                    } else if dest.is_atom() {
+                        self.vm.registry.lock().unwrap().whereis(dest.atom_val())
+                    } else {
+                        None
+                    };
+                    let Some(pid) = pid else {
+                        self.exit(DataObject::atom("badarg"));
+                        return true;
+                    };
+                    let msg = MBuf::copy(self.get(&Reg::X(1)).unwrap());
+                    self.vm.send(&pid, msg);
+                }
+                Instruction::LoopRec { lbl, dest } => {
+                    if self.message_area.peek().is_none() {
+                        self.fetch_messages();
+                    }
+                    match self.message_area.peek() {
+                        Some(msg) => self.put(dest, msg),
+                        None => self.pcb.set_ip(*lbl),
+                    }
+                }
+                Instruction::LoopRecEnd { lbl } => {
+                    self.message_area.next();
+                    self.pcb.set_ip(*lbl);
+                }
+                Instruction::RemoveMessage => {
+                    self.message_area.remove();
+                    self.pcb.cancel_timer();
+                }
+                Instruction::Wait { lbl } => {
+                    self.pcb.set_ip(*lbl);
+                    self.pcb.set_waiting();
+                    return false;
+                }
+                Instruction::WaitTimeout { lbl, time } => {
+                    self.test_heap(time.size());
+                    let time = self.src(time);
+                    if time == Term::intern("infinity") {
+                        self.pcb.set_ip(*lbl);
+                        self.pcb.set_waiting();
+                        return false;
+                    }
+                    if time.is_big() && time.big_val().sign() == Sign::Plus {
+                        // Longer than any deadline an `Instant` can hold, so never fires
+                        self.pcb.set_ip(*lbl);
+                        self.pcb.set_waiting();
+                        return false;
+                    }
+                    if !time.is_small() || time.small_val() < 0 {
+                        self.exit(DataObject::atom("timeout_value"));
+                        return true;
+                    }
+                    let ms = time.small_val();
+                    // A zero timeout falls straight through to the timeout instruction
+                    if ms > 0 {
+                        if !self.pcb.has_timer() {
+                            let deadline =
+                                Instant::now().checked_add(Duration::from_millis(ms as u64));
+                            if let Some(deadline) = deadline {
+                                self.pcb.set_timer(deadline, self.pcb.get_ip());
+                            }
+                        }
+                        self.pcb.set_ip(*lbl);
+                        self.pcb.set_waiting();
+                        return false;
+                    }
                 }
-                Instruction::Wait => todo!(),
+                Instruction::Timeout => {
+                    self.pcb.cancel_timer();
+                    self.message_area.reset_save();
+                }
+                Instruction::RecvMark { lbl } => {
+                    // Messages still in the inbound queue arrived before the mark too
+                    self.fetch_messages();
+                    self.message_area.mark(*lbl);
+                }
+                Instruction::RecvSet { lbl } => self.message_area.set_to_mark(*lbl),
+            }
+            // Calls and returns between modules switch the code being run
+            if !Arc::ptr_eq(&code, &self.code) {
+                code = self.code.clone();
+            }
+            if self.pcb.dec_fcalls(cost) {
+                return false;
             }
         }
         true
@@ -253,20 +1300,88 @@ impl Process {
         &mut self.pcb
     }
 
+    pub fn migrate(&mut self, scheduler: usize) {
+        self.pcb.migrate(scheduler);
+    }
+
+    /// Sends `message` to this process while it is locked anyway
     pub fn write_to_mailbox(&mut self, message: DataObject) {
-        self.message_area.add_msg(message);
-        println!("messages: {:?}", self.message_area);
+        self.inbox.push(MBuf::build(&message));
+        self.pcb.wake();
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::{Arc, Mutex, mpsc};
+/// Characters of a proper list of code points, like the ones `atom_to_list/1` makes
+fn char_list(mut list: Term) -> Option<String> {
+    let mut s = String::new();
+    while list.is_list() {
+        let c = list.hd();
+        if !c.is_small() {
+            return None;
+        }
+        s.push(char::from_u32(c.small_val().try_into().ok()?)?);
+        list = list.tl();
+    }
+    list.is_nil().then_some(s)
+}
 
-    use crate::{
-        instr::Instruction,
-        mem::{DataObject, PID, stack::Reg},
-        vm::Process,
+/// Atom called `name`, failing with `system_limit` if the name is too long or the atom table is
+/// full
+fn new_atom(name: &str) -> Result<Term, DataObject> {
+    let system_limit = || DataObject::atom("system_limit");
+    if name.chars().count() > atom::MAX_CHARS {
+        return Err(system_limit());
+    }
+    atom::try_intern(name)
+        .map(Term::atom)
+        .ok_or_else(system_limit)
+}
+
+/// Bytes of an iolist, which is a possibly deep list of bytes and binaries. The tail of any of
+/// the lists can be a binary too.
+fn iolist_bytes(list: Term) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // Terms left to flatten, and whether they are the tail of a list
+    let mut todo = vec![(list, true)];
+    while let Some((term, tail)) = todo.pop() {
+        if term.is_list() {
+            todo.push((term.tl(), true));
+            todo.push((term.hd(), false));
+        } else if term.is_binary() {
+            term.with_bytes(|b| bytes.extend_from_slice(b));
+        } else if term.is_small() && !tail {
+            bytes.push(term.small_val().try_into().ok()?);
+        } else if !term.is_nil() {
+            return None;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex, PoisonError, mpsc},
+        thread,
+        time::Duration,
+    };
+
+    use num_bigint::BigInt;
+
+    use crate::{
+        bif::Bif,
+        instr::{Instruction, Src},
+        mem::{
+            DataObject, NUM_X_REGS, PID, Ref, atom,
+            heap::MIN_HEAP_SIZE,
+            stack::Reg,
+            term::{MAX_SMALL, MIN_SMALL, Term},
+        },
+        module::Module,
+        parse_module, parse_str,
+        pcb::{Priority, Reductions},
+        scheduler::RunQueue,
+        vm::{Process, Shared, VM, VMCmd},
     };
 
     fn run_test<const I: usize, const R: usize>(
@@ -274,13 +1389,14 @@ mod tests {
         regs: [(Reg, DataObject); R],
     ) {
         let (tx, _) = mpsc::channel();
-        let registers = Arc::new(Mutex::new(core::array::from_fn(|_| DataObject::Nil)));
-        let mut process = Process::new(PID::new(0, 0), instrs.to_vec(), registers, tx);
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", instrs.to_vec()),
+            Shared::new(tx),
+        );
         process.run();
         for (reg, value) in regs {
-            process.get(&reg, |v| {
-                assert_eq!(v, Some(value));
-            })
+            assert_eq!(process.get(&reg).map(DataObject::from), Some(value));
         }
     }
 
@@ -326,6 +1442,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn references() {
+        let process = run_str(
+            "{bif, make_ref, {x, 0}}.
+{bif, make_ref, {x, 1}}.
+{move, {x, 2}, 0}.
+{move, {x, 3}, 0}.
+{is_reference, 1, {x, 0}}.
+{move, {x, 2}, 1}.
+{label, 1}.
+{is_eq, 2, {x, 0}, {x, 1}}.
+{move, {x, 3}, 1}.
+{label, 2}.",
+        );
+        let [a, b] = [0, 1].map(|i| process.get(&Reg::X(i)).unwrap());
+        assert!(a.is_ref() && b.is_ref());
+        assert_ne!(a.ref_val(), b.ref_val());
+        assert_eq!(process.get(&Reg::X(2)), Some(Term::small(0)));
+        assert_eq!(process.get(&Reg::X(3)), Some(Term::small(1)));
+    }
+
     #[test]
     fn comparisons() {
         run_test(
@@ -451,4 +1588,1464 @@ mod tests {
             [(Reg::X(0), DataObject::Nil)],
         );
     }
+
+    #[test]
+    fn heap() {
+        let (tx, _) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", vec![Instruction::TestHeap { need: 3 }]),
+            Shared::new(tx),
+        );
+        process.run();
+        assert_eq!(process.get(&Reg::Htop), Some(Term::small(0)));
+        let tuple = process.heap.tuple(&[Term::small(1), Term::small(2)]);
+        process.put(&Reg::X(0), tuple);
+        assert_eq!(process.get(&Reg::Htop), Some(Term::small(3)));
+        assert_eq!(process.get(&Reg::X(0)).unwrap().element(2), Term::small(2));
+    }
+
+    #[test]
+    fn tuples() {
+        let ok = || DataObject::atom("ok");
+        run_test(
+            [
+                Instruction::Move {
+                    dest: Reg::X(0),
+                    src: DataObject::Small(42),
+                },
+                Instruction::PutTuple2 {
+                    dest: Reg::X(1),
+                    elems: vec![
+                        Src::Lit(ok()),
+                        Src::Reg(Reg::X(0)),
+                        Src::Lit(DataObject::Nil),
+                    ],
+                },
+                Instruction::GetTupleElement {
+                    src: Reg::X(1),
+                    index: 1,
+                    dest: Reg::X(2),
+                },
+                Instruction::SetTupleElement {
+                    elem: Src::Lit(DataObject::Tuple(vec![DataObject::Small(1)])),
+                    tuple: Reg::X(1),
+                    index: 2,
+                },
+                Instruction::Move {
+                    dest: Reg::X(3),
+                    src: DataObject::Small(1),
+                },
+                Instruction::Bif {
+                    bif: Bif::Element,
+                    args: vec![Reg::X(3), Reg::X(1)],
+                    ret: Reg::X(4),
+                },
+                Instruction::Bif {
+                    bif: Bif::TupleSize,
+                    args: vec![Reg::X(1)],
+                    ret: Reg::X(5),
+                },
+            ],
+            [
+                (
+                    Reg::X(1),
+                    DataObject::Tuple(vec![
+                        ok(),
+                        DataObject::Small(42),
+                        DataObject::Tuple(vec![DataObject::Small(1)]),
+                    ]),
+                ),
+                (Reg::X(2), DataObject::Small(42)),
+                (Reg::X(4), ok()),
+                (Reg::X(5), DataObject::Small(3)),
+            ],
+        );
+
+        run_test(
+            [
+                Instruction::PutTuple2 {
+                    dest: Reg::X(0),
+                    elems: vec![Src::Lit(ok()), Src::Lit(ok())],
+                },
+                Instruction::IsTuple {
+                    lbl: 3,
+                    arg: Reg::X(0),
+                },
+                Instruction::Move {
+                    dest: Reg::X(1),
+                    src: DataObject::Small(0),
+                },
+                Instruction::TestArity {
+                    lbl: 5,
+                    arg: Reg::X(0),
+                    arity: 3,
+                },
+                Instruction::Move {
+                    dest: Reg::X(2),
+                    src: DataObject::Small(0),
+                },
+            ],
+            [
+                (Reg::X(1), DataObject::Nil),
+                (Reg::X(2), DataObject::Small(0)),
+            ],
+        );
+    }
+
+    #[test]
+    fn process_flag() {
+        let process = run_str(
+            "{move, {x, 0}, priority}.
+{move, {x, 1}, high}.
+{bif, process_flag, {x, 0}, {x, 1}, {x, 2}}.
+{move, {x, 1}, low}.
+{bif, process_flag, {x, 0}, {x, 1}, {x, 3}}.",
+        );
+        assert_eq!(
+            process.get(&Reg::X(2)).map(DataObject::from),
+            Some(DataObject::atom("normal"))
+        );
+        assert_eq!(
+            process.get(&Reg::X(3)).map(DataObject::from),
+            Some(DataObject::atom("high"))
+        );
+        assert_eq!(process.pcb().priority(), Priority::Low);
+
+        let process = run_str(
+            "{move, {x, 0}, priority}.
+{move, {x, 1}, urgent}.
+{bif, process_flag, {x, 0}, {x, 1}, {x, 2}}.",
+        );
+        assert_eq!(process.pcb().priority(), Priority::Normal);
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+    }
+
+    #[test]
+    fn reductions() {
+        let mut process = process_str(
+            "{move, {x, 0}, 0}.
+{label, 1}.
+{add, {x, 0}, {x, 1}, {x, 0}}.
+{jmp, 1}.",
+        );
+        process.put(&Reg::X(1), Term::small(1));
+        process.pcb_mut().set_reductions(Reductions {
+            budget: 20,
+            ..Reductions::default()
+        });
+        // A loop without calls still gets preempted once the budget is used up
+        assert!(!process.run());
+        assert!(process.pcb().is_runnable());
+        assert_eq!(process.get(&Reg::X(0)), Some(Term::small(10)));
+        assert_eq!(process.pcb().get_fcalls(), 20);
+
+        // and picks up where it left off
+        assert!(!process.run());
+        assert_eq!(process.get(&Reg::X(0)), Some(Term::small(20)));
+
+        let mut process = process_str("{bif, garbage_collect, {x, 0}}.");
+        process.pcb_mut().set_reductions(Reductions {
+            budget: 100,
+            bif: 5,
+            gc: 20,
+            ..Reductions::default()
+        });
+        assert!(process.run());
+        assert_eq!(process.pcb().get_fcalls(), 75);
+    }
+
+    #[test]
+    fn links() {
+        let (tx, rx) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new(
+                "test",
+                parse_str(
+                    "{move, {x, 0}, {pid, 0, 1}}.
+{bif, link, {x, 0}, {x, 1}}.
+{bif, tuple_size, {x, 1}, {x, 2}}.",
+                ),
+            ),
+            Shared::new(tx),
+        );
+        assert!(process.run());
+        let Ok(VMCmd::Link(from, to)) = rx.try_recv() else {
+            panic!("expected link")
+        };
+        assert_eq!((from, to), (PID::new(0, 0), PID::new(0, 1)));
+        // Crashing takes linked processes down too
+        let Ok(VMCmd::Exit {
+            from,
+            to,
+            reason,
+            linked: true,
+        }) = rx.try_recv()
+        else {
+            panic!("expected exit signal")
+        };
+        assert_eq!((from, to), (PID::new(0, 0), PID::new(0, 1)));
+        assert_eq!(reason, DataObject::atom("badarg"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn exit_signals() {
+        let atom = DataObject::atom;
+        let (tx, rx) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", Vec::new()),
+            Shared::new(tx.clone()),
+        );
+        process.pcb_mut().link(PID::new(0, 1));
+        process.exit_signal(PID::new(0, 2), atom("normal"), false);
+        assert!(!process.pcb().is_exiting());
+        process.exit_signal(PID::new(0, 2), atom("kill"), false);
+        assert_eq!(process.pcb().exit_reason(), Some(&atom("killed")));
+        let Ok(VMCmd::Exit { to, reason, .. }) = rx.try_recv() else {
+            panic!("expected exit signal")
+        };
+        assert_eq!((to, reason), (PID::new(0, 1), atom("killed")));
+
+        // Exit signals from links only count while the link is there
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", Vec::new()),
+            Shared::new(tx.clone()),
+        );
+        process.exit_signal(PID::new(0, 1), atom("crash"), true);
+        assert!(!process.pcb().is_exiting());
+        process.pcb_mut().link(PID::new(0, 1));
+        process.exit_signal(PID::new(0, 1), atom("crash"), true);
+        assert_eq!(process.pcb().exit_reason(), Some(&atom("crash")));
+
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", Vec::new()),
+            Shared::new(tx),
+        );
+        process.pcb_mut().set_trap_exit(true);
+        process.pcb_mut().link(PID::new(0, 1));
+        process.exit_signal(PID::new(0, 1), atom("crash"), true);
+        assert!(!process.pcb().is_exiting());
+        process.fetch_messages();
+        assert_eq!(
+            process.message_area.peek().map(DataObject::from),
+            Some(DataObject::Tuple(vec![
+                atom("EXIT"),
+                DataObject::Pid(PID::new(0, 1)),
+                atom("crash"),
+            ]))
+        );
+        // `kill` can't be trapped
+        process.exit_signal(PID::new(0, 2), atom("kill"), false);
+        assert_eq!(process.pcb().exit_reason(), Some(&atom("killed")));
+    }
+
+    #[test]
+    fn monitors() {
+        let atom = DataObject::atom;
+        let (tx, rx) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", Vec::new()),
+            Shared::new(tx),
+        );
+        let monitor = |process: &mut Process| {
+            let args = [Term::intern("process"), Term::pid(&PID::new(0, 1))];
+            process.call_bif(Bif::Monitor, &args).unwrap()
+        };
+
+        let reference = monitor(&mut process);
+        let Ok(VMCmd::Monitor {
+            watcher,
+            target,
+            reference: sent,
+        }) = rx.try_recv()
+        else {
+            panic!("expected monitor")
+        };
+        assert_eq!((watcher, target), (PID::new(0, 0), PID::new(0, 1)));
+        assert_eq!(reference.ref_val(), sent);
+
+        process.down(sent, PID::new(0, 1), atom("crash"));
+        process.fetch_messages();
+        let down = DataObject::Tuple(vec![
+            atom("DOWN"),
+            DataObject::Refer(sent),
+            atom("process"),
+            DataObject::Pid(PID::new(0, 1)),
+            atom("crash"),
+        ]);
+        assert_eq!(
+            process.message_area.peek().map(DataObject::from),
+            Some(down)
+        );
+        // The monitor is gone after it fired
+        process.down(sent, PID::new(0, 1), atom("crash"));
+        assert!(process.inbox().is_empty());
+
+        let opts = DataObject::list(vec![atom("flush"), atom("info")], DataObject::Nil);
+        process.test_heap(opts.size());
+        let opts = process.heap.build(&opts);
+        let res = process.call_bif(Bif::DemonitorOpts, &[reference, opts]);
+        assert_eq!(res, Ok(Term::intern("false")));
+        assert!(process.message_area.peek().is_none());
+
+        // Demonitoring an active monitor tells the monitored process and drops later DOWNs
+        let reference = monitor(&mut process);
+        rx.try_recv().unwrap();
+        let res = process.call_bif(Bif::Demonitor, &[reference]);
+        assert_eq!(res, Ok(Term::intern("true")));
+        let Ok(VMCmd::Demonitor { target, .. }) = rx.try_recv() else {
+            panic!("expected demonitor")
+        };
+        assert_eq!(target, PID::new(0, 1));
+        process.down(reference.ref_val(), PID::new(0, 1), atom("crash"));
+        assert!(process.inbox().is_empty());
+    }
+
+    #[test]
+    fn down_on_exit() {
+        let (tx, rx) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new("test", Vec::new()),
+            Shared::new(tx),
+        );
+        let reference = Ref::new();
+        process.pcb_mut().monitored_by(reference, PID::new(0, 1));
+        assert!(process.run());
+        let Ok(VMCmd::Down {
+            to,
+            reference: sent,
+            from,
+            reason,
+        }) = rx.try_recv()
+        else {
+            panic!("expected down")
+        };
+        assert_eq!((to, from), (PID::new(0, 1), PID::new(0, 0)));
+        assert_eq!(sent, reference);
+        assert_eq!(reason, DataObject::atom("normal"));
+    }
+
+    #[test]
+    fn registry() {
+        let (tx, _) = mpsc::channel();
+        let vm = Shared::new(tx);
+        let process = vm
+            .procs
+            .insert(|num| {
+                Process::new(
+                    PID::new(0, num),
+                    Module::new(
+                        "test",
+                        parse_str(
+                            "{move, {x, 0}, srv}.
+{move, {x, 1}, {pid, 0, 0}}.
+{bif, register, {x, 0}, {x, 1}, {x, 2}}.
+{bif, whereis, {x, 0}, {x, 3}}.
+{move, {x, 4}, other}.
+{bif, whereis, {x, 4}, {x, 4}}.
+{move, {x, 1}, hello}.
+{send}.",
+                        ),
+                    ),
+                    vm.clone(),
+                )
+            })
+            .unwrap();
+        let mut process = process.lock().unwrap();
+        assert!(process.run());
+        assert_eq!(process.get(&Reg::X(2)), Some(Term::intern("true")));
+        assert_eq!(process.get(&Reg::X(3)), Some(Term::pid(&PID::new(0, 0))));
+        assert_eq!(process.get(&Reg::X(4)), Some(Term::intern("undefined")));
+        let msgs: Vec<_> = process
+            .inbox()
+            .take_all()
+            .iter()
+            .map(|mbuf| DataObject::from(mbuf.msg()))
+            .collect();
+        assert_eq!(msgs, vec![DataObject::atom("hello")]);
+        // The name is released once the process is done
+        assert_eq!(
+            vm.registry.lock().unwrap().whereis(atom::intern("srv")),
+            None
+        );
+
+        let process = run_str(
+            "{move, {x, 0}, nobody}.
+{send}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+
+        // Dead processes can't be registered
+        let new = |code: &str| {
+            vm.procs
+                .insert(|num| {
+                    Process::new(
+                        PID::new(0, num),
+                        Module::new("test", parse_str(code)),
+                        vm.clone(),
+                    )
+                })
+                .unwrap()
+        };
+        let dead = new("{move, {x, 0}, ok}.");
+        let dead_pid = dead.lock().unwrap().id().expect_pid().clone();
+        assert!(dead.lock().unwrap().run());
+        let process = new(&format!(
+            "{{move, {{x, 0}}, ghost}}.
+{{move, {{x, 1}}, {{pid, 0, {}}}}}.
+{{bif, register, {{x, 0}}, {{x, 1}}, {{x, 2}}}}.",
+            dead_pid.number()
+        ));
+        let mut process = process.lock().unwrap();
+        assert!(process.run());
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+    }
+
+    #[test]
+    fn trap_exit() {
+        let process = run_str(
+            "{move, {x, 0}, trap_exit}.
+{move, {x, 1}, true}.
+{bif, process_flag, {x, 0}, {x, 1}, {x, 2}}.
+{bif, process_flag, {x, 0}, {x, 1}, {x, 3}}.",
+        );
+        assert_eq!(
+            process.get(&Reg::X(2)).map(DataObject::from),
+            Some(DataObject::atom("false"))
+        );
+        assert_eq!(
+            process.get(&Reg::X(3)).map(DataObject::from),
+            Some(DataObject::atom("true"))
+        );
+        assert!(process.pcb().trap_exit());
+    }
+
+    #[test]
+    fn tuple_syntax() {
+        let (tx, _) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new(
+                "test",
+                parse_str(
+                    "{move, {x, 0}, 7}.
+{put_tuple2, {x, 1}, {list, ok, {x, 0}}}.
+{get_tuple_element, {x, 1}, 0, {x, 2}}.
+{move, {x, 3}, 3}.
+{bif, element, {x, 3}, {x, 1}, {x, 4}}.",
+                ),
+            ),
+            Shared::new(tx),
+        );
+        process.run();
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::Tuple(vec![
+                DataObject::atom("ok"),
+                DataObject::Small(7)
+            ]))
+        );
+        assert_eq!(
+            process.get(&Reg::X(2)).map(DataObject::from),
+            Some(DataObject::atom("ok"))
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+    }
+
+    #[test]
+    fn set_promoted_tuple_element() {
+        let process = run_str(
+            "{put_tuple2, {x, 0}, {list, ok, ok}}.
+{bif, garbage_collect, {x, 1}}.
+{set_tuple_element, {literal, {1, 2}}, {x, 0}, 1}.
+{test_heap, 1000}.",
+        );
+        let tuple = process.get(&Reg::X(0)).unwrap();
+        assert!(process.old_heap.contains(tuple.ptr()));
+        assert_eq!(
+            DataObject::from(tuple),
+            DataObject::Tuple(vec![
+                DataObject::atom("ok"),
+                DataObject::Tuple(vec![DataObject::Small(1), DataObject::Small(2)]),
+            ])
+        );
+    }
+
+    fn process_str(s: &str) -> Process {
+        let (tx, _) = mpsc::channel();
+        Process::new(
+            PID::new(0, 0),
+            Module::new("test", parse_str(s)),
+            Shared::new(tx),
+        )
+    }
+
+    /// Runs `process` until it finishes or blocks, like a scheduler would
+    fn run_to_end(process: &mut Process) {
+        while !process.run() && process.pcb().is_runnable() {}
+    }
+
+    fn run_str(s: &str) -> Process {
+        let mut process = process_str(s);
+        run_to_end(&mut process);
+        process
+    }
+
+    #[test]
+    fn bignums() {
+        let process = run_str(
+            "{move, {x, 0}, 576460752303423487}.
+{bif, '+', {x, 0}, {x, 0}, {x, 1}}.
+{move, {x, 2}, 100000000000000000000}.
+{bif, '*', {x, 2}, {x, 2}, {x, 3}}.
+{bif, garbage_collect, {x, 9}}.
+{bif, 'div', {x, 3}, {x, 2}, {x, 4}}.
+{bif, '-', {x, 1}, {x, 0}, {x, 5}}.
+{add, {x, 1}, {x, 5}, {x, 6}}.
+{move, {x, 8}, 58}.
+{bif, bsr, {x, 6}, {x, 8}, {x, 7}}.",
+        );
+        let max_small = BigInt::from(MAX_SMALL);
+        let get = |i| process.get(&Reg::X(i)).map(DataObject::from);
+        assert_eq!(get(1), Some(DataObject::Big(&max_small * 2)));
+        let ten_pow_20 = BigInt::from(10).pow(20);
+        assert_eq!(get(2), Some(DataObject::Big(ten_pow_20.clone())));
+        assert_eq!(get(3), Some(DataObject::Big(&ten_pow_20 * &ten_pow_20)));
+        assert_eq!(get(4), Some(DataObject::Big(ten_pow_20)));
+        // Results that fit are smalls again
+        assert!(process.get(&Reg::X(5)).unwrap().is_small());
+        assert_eq!(get(5), Some(DataObject::Small(MAX_SMALL)));
+        assert_eq!(get(6), Some(DataObject::Big(max_small * 3)));
+        assert_eq!(get(7), Some(DataObject::Small(5)));
+
+        let process = run_str(
+            "{move, {x, 0}, 1}.
+{move, {x, 1}, 0}.
+{bif, 'div', {x, 0}, {x, 1}, {x, 2}}.
+{move, {x, 2}, ok}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarith"))
+        );
+        assert_eq!(process.get(&Reg::X(2)), Some(Term::NIL));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let process = run_str(
+            "{move, {x, 0}, -7}.
+{gc_bif, '*', 0, 1, [{x, 0}, 3], {x, 1}}.
+{gc_bif, 'div', 0, 2, [{x, 1}, 2], {x, 2}}.
+{gc_bif, 'rem', 0, 2, [{x, 1}, 2], {x, 3}}.
+{gc_bif, '-', 0, 1, [{x, 0}], {x, 4}}.
+{gc_bif, abs, 0, 1, [{x, 0}], {x, 5}}.
+{gc_bif, bnot, 0, 1, [{x, 0}], {x, 6}}.
+{gc_bif, band, 0, 1, [{x, 0}, 255], {x, 7}}.
+{gc_bif, '-', 0, 1, [-576460752303423488], {x, 8}}.
+{gc_bif, 'div', 1, 1, [{x, 0}, 0], {x, 9}}.
+{move, {x, 9}, no_jump}.
+{label, 1}.
+{gc_bif, '+', 0, 1, [{x, 0}, one], {x, 10}}.
+{move, {x, 10}, no_error}.",
+        );
+        let get = |i| process.get(&Reg::X(i)).map(DataObject::from);
+        assert_eq!(get(1), Some(DataObject::Small(-21)));
+        // Division rounds towards zero and the remainder has the sign of the dividend
+        assert_eq!(get(2), Some(DataObject::Small(-10)));
+        assert_eq!(get(3), Some(DataObject::Small(-1)));
+        assert_eq!(get(4), Some(DataObject::Small(7)));
+        assert_eq!(get(5), Some(DataObject::Small(7)));
+        assert_eq!(get(6), Some(DataObject::Small(6)));
+        assert_eq!(get(7), Some(DataObject::Small(249)));
+        assert_eq!(get(8), Some(DataObject::Big(-BigInt::from(MIN_SMALL))));
+        // A failure jumps to the fail label, or raises the error without one
+        assert_eq!(get(9), Some(DataObject::Nil));
+        assert_eq!(get(10), Some(DataObject::Nil));
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarith"))
+        );
+    }
+
+    #[test]
+    fn floats() {
+        let process = run_str(
+            "{move, {x, 0}, 1.5}.
+{move, {x, 1}, 2}.
+{fclearerror}.
+{fmove, {x, 0}, {fr, 0}}.
+{fconv, {x, 1}, {fr, 1}}.
+{fadd, {fr, 0}, {fr, 1}, {fr, 2}}.
+{fmul, {fr, 2}, {fr, 1}, {fr, 2}}.
+{fnegate, {fr, 2}, {fr, 2}}.
+{fcheckerror}.
+{fmove, {fr, 2}, {x, 2}}.
+{gc_bif, '/', 0, 2, [{x, 1}, 4], {x, 3}}.
+{gc_bif, '+', 0, 2, [{x, 0}, {x, 1}], {x, 4}}.
+{is_float, 1, {x, 1}}.
+{is_float, 2, {x, 3}}.
+{label, 1}.
+{move, {x, 5}, not_float}.
+{label, 2}.
+{move, {x, 7}, 2.0}.
+{is_eq, 3, {x, 1}, {x, 7}}.
+{move, {x, 6}, not_equal}.
+{label, 3}.",
+        );
+        let get = |i| process.get(&Reg::X(i)).map(DataObject::from);
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("normal"))
+        );
+        assert_eq!(get(2), Some(DataObject::Float(-7.0)));
+        assert_eq!(get(3), Some(DataObject::Float(0.5)));
+        assert_eq!(get(4), Some(DataObject::Float(3.5)));
+        assert_eq!(get(5), Some(DataObject::Nil));
+        // Integers and floats compare by value
+        assert_eq!(get(6), Some(DataObject::Nil));
+
+        let process = run_str(
+            "{move, {x, 0}, 0.0}.
+{fclearerror}.
+{fmove, {x, 0}, {fr, 0}}.
+{fdiv, {fr, 0}, {fr, 0}, {fr, 1}}.
+{fmove, {fr, 1}, {x, 1}}.
+{fcheckerror}.
+{move, {x, 2}, ok}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarith"))
+        );
+        assert_eq!(process.get(&Reg::X(1)), Some(Term::NIL));
+        assert_eq!(process.get(&Reg::X(2)), Some(Term::NIL));
+    }
+
+    /// Held by tests that make atoms with BIFs or change the atom limit, which every VM shares
+    static ATOM_LIMIT: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn atoms() {
+        let _limit = ATOM_LIMIT.lock().unwrap_or_else(PoisonError::into_inner);
+        // "atom_bif_test"
+        let process = run_str(
+            "{move, {x, 0}, hello}.
+{bif, atom_to_list, {x, 0}, {x, 1}}.
+{bif, list_to_atom, {x, 1}, {x, 2}}.
+{bif, list_to_existing_atom, {x, 1}, {x, 3}}.
+{move, {x, 4}, [97, 116, 111, 109, 95, 98, 105, 102, 95, 116, 101, 115, 116]}.
+{bif, list_to_atom, {x, 4}, {x, 5}}.",
+        );
+        let get = |i| process.get(&Reg::X(i)).map(DataObject::from);
+        let hello = "hello"
+            .chars()
+            .map(|c| DataObject::Small(c as i64))
+            .collect();
+        assert_eq!(get(1), Some(DataObject::list(hello, DataObject::Nil)));
+        assert_eq!(get(2), Some(DataObject::atom("hello")));
+        assert_eq!(get(3), Some(DataObject::atom("hello")));
+        assert_eq!(
+            process
+                .get(&Reg::X(5))
+                .map(|atom| atom::name(atom.atom_val())),
+            Some("atom_bif_test".to_string())
+        );
+
+        // "atom_bif_missing" was never made into an atom
+        let process = run_str(
+            "{move, {x, 0}, [97, 116, 111, 109, 95, 98, 105, 102, 95, 109, 105, 115, 115, 105, 110, 103]}.
+{bif, list_to_existing_atom, {x, 0}, {x, 1}}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+        let process = run_str(
+            "{move, {x, 0}, [a]}.
+{bif, list_to_atom, {x, 0}, {x, 1}}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+        let process = run_str(&format!(
+            "{{move, {{x, 0}}, [{}]}}.
+{{bif, list_to_atom, {{x, 0}}, {{x, 1}}}}.",
+            ["97"; 256].join(", ")
+        ));
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("system_limit"))
+        );
+    }
+
+    #[test]
+    fn atom_table_full() {
+        let _limit = ATOM_LIMIT.lock().unwrap_or_else(PoisonError::into_inner);
+        atom::set_limit(0);
+        let chars: Vec<_> = "atom_table_full".bytes().map(|c| c.to_string()).collect();
+        let process = run_str(&format!(
+            "{{move, {{x, 0}}, [{}]}}.
+{{bif, list_to_atom, {{x, 0}}, {{x, 1}}}}.",
+            chars.join(", ")
+        ));
+        let full = process.pcb().exit_reason().cloned();
+        // Errors the runtime raises itself still work
+        let process = run_str(
+            "{move, {x, 0}, 1}.
+{bif, atom_to_list, {x, 0}, {x, 1}}.",
+        );
+        let badarg = process.pcb().exit_reason().cloned();
+        let runtime = DataObject::atom("atom_table_full_runtime");
+        atom::set_limit(atom::DEFAULT_LIMIT);
+
+        assert_eq!(full, Some(DataObject::atom("system_limit")));
+        assert_eq!(badarg, Some(DataObject::atom("badarg")));
+        assert_eq!(runtime, DataObject::atom("atom_table_full_runtime"));
+        assert!(atom::existing("atom_table_full").is_none());
+    }
+
+    #[test]
+    fn binaries() {
+        let _limit = ATOM_LIMIT.lock().unwrap_or_else(PoisonError::into_inner);
+        let long = "binary ".repeat(10);
+        let process = run_str(&format!(
+            "{{move, {{x, 0}}, <<\"hello world\">>}}.
+{{is_binary, 1, {{x, 0}}}}.
+{{move, {{x, 9}}, not_binary}}.
+{{label, 1}}.
+{{gc_bif, byte_size, 0, 1, [{{x, 0}}], {{x, 1}}}}.
+{{gc_bif, bit_size, 0, 1, [{{x, 0}}], {{x, 2}}}}.
+{{gc_bif, binary_part, 0, 1, [{{x, 0}}, 6, 5], {{x, 3}}}}.
+{{move, {{x, 4}}, latin1}}.
+{{bif, binary_to_atom, {{x, 3}}, {{x, 4}}, {{x, 5}}}}.
+{{move, {{x, 6}}, [<<1, 2>>, [3, <<>>] | <<4>>]}}.
+{{bif, list_to_binary, {{x, 6}}, {{x, 7}}}}.
+{{bif, binary_to_list, {{x, 7}}, {{x, 8}}}}.
+{{move, {{x, 10}}, <<\"{long}\">>}}.
+{{bif, garbage_collect, {{x, 11}}}}.
+{{gc_bif, binary_part, 0, 1, [{{x, 10}}, 7, -7], {{x, 12}}}}.
+{{is_bitstr, 2, {{x, 4}}}}.
+{{move, {{x, 13}}, not_bitstring}}.
+{{label, 2}}."
+        ));
+        let get = |i| process.get(&Reg::X(i)).map(DataObject::from);
+        let binary = |bytes: &[u8]| Some(DataObject::Binary(Arc::new(bytes.to_vec())));
+        assert_eq!(get(9), Some(DataObject::Nil));
+        assert_eq!(get(1), Some(DataObject::Small(11)));
+        assert_eq!(get(2), Some(DataObject::Small(88)));
+        assert_eq!(get(3), binary(b"world"));
+        assert_eq!(get(5), Some(DataObject::atom("world")));
+        assert_eq!(get(7), binary(&[1, 2, 3, 4]));
+        let bytes = (1..=4).map(DataObject::Small).collect();
+        assert_eq!(get(8), Some(DataObject::list(bytes, DataObject::Nil)));
+        assert!(process.get(&Reg::X(10)).unwrap().is_refc_binary());
+        assert_eq!(get(10), binary(long.as_bytes()));
+        assert_eq!(get(12), binary(b"binary "));
+        assert_eq!(get(13), Some(DataObject::atom("not_bitstring")));
+
+        for (code, reason) in [
+            (
+                "{gc_bif, binary_part, 0, 1, [<<1, 2>>, 1, 2], {x, 0}}.",
+                "badarg",
+            ),
+            ("{gc_bif, byte_size, 0, 1, [[1, 2]], {x, 0}}.", "badarg"),
+            (
+                "{move, {x, 0}, [1 | 2]}.
+{bif, list_to_binary, {x, 0}, {x, 1}}.",
+                "badarg",
+            ),
+            (
+                "{move, {x, 0}, [256]}.
+{bif, list_to_binary, {x, 0}, {x, 1}}.",
+                "badarg",
+            ),
+            (
+                "{move, {x, 0}, <<255>>}.
+{move, {x, 1}, utf8}.
+{bif, binary_to_atom, {x, 0}, {x, 1}, {x, 2}}.",
+                "badarg",
+            ),
+        ] {
+            let process = run_str(code);
+            assert_eq!(
+                process.pcb().exit_reason(),
+                Some(&DataObject::atom(reason)),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn lists() {
+        let process = run_str(
+            "{move, {x, 0}, {literal, [1, 2, 3]}}.
+{move, {x, 1}, 0}.
+{label, 1}.
+{is_nil, 2, {x, 0}}.
+{get_list, {x, 0}, {x, 2}, {x, 0}}.
+{add, {x, 1}, {x, 2}, {x, 1}}.
+{jmp, 1}.
+{label, 2}.
+{put_list, {x, 1}, [], {x, 3}}.
+{put_list, a, {x, 3}, {x, 3}}.
+{get_hd, {x, 3}, {x, 4}}.
+{get_tl, {x, 3}, {x, 5}}.
+{move, {x, 6}, {literal, [{ok, 1} | 2]}}.",
+        );
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::Small(6))
+        );
+        assert_eq!(
+            process.get(&Reg::X(3)).map(DataObject::from),
+            Some(DataObject::list(
+                vec![DataObject::atom("a"), DataObject::Small(6)],
+                DataObject::Nil
+            ))
+        );
+        assert_eq!(
+            process.get(&Reg::X(4)).map(DataObject::from),
+            Some(DataObject::atom("a"))
+        );
+        assert_eq!(
+            process.get(&Reg::X(5)).map(DataObject::from),
+            Some(DataObject::list(
+                vec![DataObject::Small(6)],
+                DataObject::Nil
+            ))
+        );
+        assert_eq!(
+            process.get(&Reg::X(6)).map(DataObject::from),
+            Some(DataObject::list(
+                vec![DataObject::Tuple(vec![
+                    DataObject::atom("ok"),
+                    DataObject::Small(1)
+                ])],
+                DataObject::Small(2)
+            ))
+        );
+    }
+
+    #[test]
+    fn list_type_tests() {
+        let process = run_str(
+            "{move, {x, 0}, []}.
+{is_list, 1, {x, 0}}.
+{move, {x, 1}, bad}.
+{label, 1}.
+{is_nonempty_list, 2, {x, 0}}.
+{move, {x, 2}, good}.
+{label, 2}.
+{put_list, 1, {x, 0}, {x, 0}}.
+{is_nonempty_list, 3, {x, 0}}.
+{move, {x, 3}, bad}.
+{label, 3}.
+{is_nil, 4, {x, 0}}.
+{move, {x, 4}, good}.
+{label, 4}.",
+        );
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::Nil)
+        );
+        assert_eq!(
+            process.get(&Reg::X(2)).map(DataObject::from),
+            Some(DataObject::atom("good"))
+        );
+        assert_eq!(
+            process.get(&Reg::X(3)).map(DataObject::from),
+            Some(DataObject::Nil)
+        );
+        assert_eq!(
+            process.get(&Reg::X(4)).map(DataObject::from),
+            Some(DataObject::atom("good"))
+        );
+    }
+
+    #[test]
+    fn long_lists() {
+        // The list doesn't fit on the initial heap so it gets garbage collected while being built
+        let process = run_str(
+            "{move, {x, 0}, []}.
+{move, {x, 1}, 10000}.
+{move, {x, 2}, 0}.
+{move, {x, 3}, 1}.
+{label, 1}.
+{add, {x, 2}, {x, 3}, {x, 2}}.
+{put_list, {x, 2}, {x, 0}, {x, 0}}.
+{is_eq, 2, {x, 2}, {x, 1}}.
+{jmp, 1}.
+{label, 2}.
+{move, {x, 4}, 0}.
+{label, 3}.
+{is_nil, 4, {x, 0}}.
+{get_list, {x, 0}, {x, 2}, {x, 0}}.
+{add, {x, 4}, {x, 2}, {x, 4}}.
+{jmp, 3}.
+{label, 4}.",
+        );
+        assert!(process.old_heap.htop() > 0);
+        assert_eq!(
+            process.get(&Reg::X(4)).map(DataObject::from),
+            Some(DataObject::Small(50005000))
+        );
+    }
+
+    // Receives the first {_, _} message and puts its second element in X1
+    const RECEIVE_PAIR: &str = "{label, 1}.
+{loop_rec, 3, {x, 0}}.
+{test_arity, 2, {x, 0}, 2}.
+{loop_rec_end, 1}.
+{label, 2}.
+{remove_message}.
+{get_tuple_element, {x, 0}, 1, {x, 1}}.
+{jmp, 4}.
+{label, 3}.
+{wait, 1}.
+{label, 4}.";
+
+    #[test]
+    fn selective_receive() {
+        let mut process = process_str(RECEIVE_PAIR);
+        process.write_to_mailbox(DataObject::atom("hello"));
+        process.write_to_mailbox(DataObject::Tuple(vec![
+            DataObject::atom("ok"),
+            DataObject::Small(5),
+        ]));
+        assert!(process.run());
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::Small(5))
+        );
+        // The message that didn't match is still there
+        assert_eq!(
+            process.message_area.peek().map(DataObject::from),
+            Some(DataObject::atom("hello"))
+        );
+    }
+
+    #[test]
+    fn wait() {
+        let mut process = process_str(RECEIVE_PAIR);
+        process.write_to_mailbox(DataObject::atom("hello"));
+        assert!(!process.run());
+        assert!(process.pcb().is_waiting());
+
+        process.write_to_mailbox(DataObject::Tuple(vec![
+            DataObject::atom("ok"),
+            DataObject::Small(1),
+        ]));
+        assert!(process.pcb().is_runnable());
+        assert!(process.run());
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::Small(1))
+        );
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let receive = |time: &str| {
+            format!(
+                "{{label, 1}}.
+{{loop_rec, 2, {{x, 0}}}}.
+{{remove_message}}.
+{{jmp, 3}}.
+{{label, 2}}.
+{{wait_timeout, 1, {time}}}.
+{{timeout}}.
+{{move, {{x, 0}}, timed_out}}.
+{{label, 3}}."
+            )
+        };
+        let timed_out = Some(DataObject::atom("timed_out"));
+
+        let process = run_str(&receive("0"));
+        assert_eq!(process.get(&Reg::X(0)).map(DataObject::from), timed_out);
+
+        let mut process = process_str(&receive("10"));
+        assert!(!process.run());
+        process.pcb_mut().check_timeout();
+        assert!(process.pcb().is_waiting());
+        thread::sleep(Duration::from_millis(20));
+        process.pcb_mut().check_timeout();
+        assert!(process.run());
+        assert_eq!(process.get(&Reg::X(0)).map(DataObject::from), timed_out);
+
+        // A message arriving in time cancels the timeout
+        let mut process = process_str(&receive("1000"));
+        assert!(!process.run());
+        process.write_to_mailbox(DataObject::Small(1));
+        assert!(process.run());
+        assert!(!process.pcb().has_timer());
+        assert_eq!(
+            process.get(&Reg::X(0)).map(DataObject::from),
+            Some(DataObject::Small(1))
+        );
+
+        let mut process = process_str(&receive("infinity"));
+        assert!(!process.run());
+        assert!(!process.pcb().has_timer());
+
+        // Timeouts past the largest small integer wait forever
+        let mut process = process_str(&receive("576460752303423488"));
+        assert!(!process.run());
+        assert!(!process.pcb().has_timer());
+
+        for time in ["-1", "1.5", "forever", "-576460752303423489"] {
+            let process = run_str(&receive(time));
+            assert_eq!(
+                process.pcb().exit_reason(),
+                Some(&DataObject::atom("timeout_value"))
+            );
+        }
+    }
+
+    #[test]
+    fn recv_mark() {
+        let mut process = process_str(
+            "{recv_mark, 1}.
+{wait, 2}.
+{label, 2}.
+{recv_set, 1}.
+{label, 1}.
+{loop_rec, 3, {x, 0}}.
+{remove_message}.
+{label, 3}.",
+        );
+        process.write_to_mailbox(DataObject::atom("old"));
+        assert!(!process.run());
+        process.write_to_mailbox(DataObject::atom("new"));
+        assert!(process.run());
+        assert_eq!(
+            process.get(&Reg::X(0)).map(DataObject::from),
+            Some(DataObject::atom("new"))
+        );
+        assert_eq!(
+            process.message_area.peek().map(DataObject::from),
+            Some(DataObject::atom("old"))
+        );
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let (tx, _) = mpsc::channel();
+        let mut process = Process::new(
+            PID::new(0, 0),
+            Module::new(
+                "test",
+                vec![Instruction::Bif {
+                    bif: Bif::GarbageCollect,
+                    args: Vec::new(),
+                    ret: Reg::X(1),
+                }],
+            ),
+            Shared::new(tx),
+        );
+        let tuple = process.heap.tuple(&[Term::small(1), Term::small(2)]);
+        process.put(&Reg::X(0), tuple);
+        process.put(&Reg::Y(0), tuple);
+        // Fill the heap with garbage so it has to be collected a few times
+        for _ in 0..1000 {
+            process.test_heap(3);
+            process.heap.tuple(&[Term::NIL, Term::NIL]);
+        }
+        assert!(process.heap.htop() > 0);
+
+        process.run();
+        assert_eq!(
+            process.get(&Reg::X(1)).map(DataObject::from),
+            Some(DataObject::atom("true"))
+        );
+        assert_eq!(process.heap.htop(), 0);
+        assert_eq!(process.old_heap.htop(), 3);
+        let tuple = process.get(&Reg::X(0)).unwrap();
+        assert_eq!(process.get(&Reg::Y(0)), Some(tuple));
+        assert_eq!(tuple.element(1), Term::small(1));
+        assert_eq!(tuple.element(2), Term::small(2));
+    }
+
+    #[test]
+    fn messages_in_fragments() {
+        let mut process = process_str(&format!(
+            "{RECEIVE_PAIR}
+{{bif, garbage_collect, {{x, 2}}}}."
+        ));
+        process.write_to_mailbox(DataObject::atom("hello"));
+        process.write_to_mailbox(DataObject::Tuple(vec![
+            DataObject::atom("ok"),
+            DataObject::Tuple(vec![DataObject::Small(1), DataObject::Small(2)]),
+        ]));
+        assert!(process.run());
+        // The matched message is still in X0 and was moved onto the heap along with its elements
+        assert_eq!(process.old_heap.htop(), 6);
+        let inner = process.get(&Reg::X(1)).unwrap();
+        assert!(process.old_heap.contains(inner.ptr()));
+        assert_eq!(
+            DataObject::from(inner),
+            DataObject::Tuple(vec![DataObject::Small(1), DataObject::Small(2)])
+        );
+        assert_eq!(
+            process.message_area.peek().map(DataObject::from),
+            Some(DataObject::atom("hello"))
+        );
+    }
+
+    #[test]
+    fn fragments_trigger_gc() {
+        // Drops every message it gets without ever allocating
+        let mut process = process_str(
+            "{label, 1}.
+{loop_rec, 2, {x, 0}}.
+{remove_message}.
+{jmp, 1}.
+{label, 2}.
+{wait, 1}.",
+        );
+        let msg = DataObject::Tuple((0..10).map(DataObject::Small).collect());
+        for _ in 0..1000 {
+            process.write_to_mailbox(msg.clone());
+            run_to_end(&mut process);
+            assert!(process.pcb().is_waiting());
+            assert!(process.message_area.fragment_words() <= MIN_HEAP_SIZE);
+        }
+    }
+
+    /// Loads the module `code` into a fresh VM and puts a process running it in the process table
+    fn spawned(code: &str) -> (Arc<Mutex<Process>>, mpsc::Receiver<VMCmd>) {
+        let (tx, rx) = mpsc::channel();
+        let vm = Shared::new(tx);
+        let code = vm.code.load(parse_module(code)).unwrap();
+        let process = vm
+            .procs
+            .insert(|num| Process::new(PID::new(0, num), code, vm.clone()))
+            .unwrap();
+        (process, rx)
+    }
+
+    #[test]
+    fn call_ext() {
+        let (process, _rx) = spawned(
+            "{module, a}.
+{move, {x, 0}, 1}.
+{call_ext, 1, {extfunc, b, add, 1}}.
+{move, {x, 1}, back}.
+{call_ext_last, 1, {extfunc, b, add, 1}, 0}.",
+        );
+        let mut process = process.lock().unwrap();
+        // Loaded after the module calling it
+        process
+            .vm
+            .code
+            .load(parse_module(
+                "{module, b}.
+{exports, [{add, 1}]}.
+{func_info, b, add, 1}.
+{add, {x, 0}, {x, 0}, {x, 0}}.
+{ret}.",
+            ))
+            .unwrap();
+        run_to_end(&mut process);
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("normal"))
+        );
+        // The first call returned to `a`, the last one returns from the process
+        assert_eq!(process.get(&Reg::X(0)), Some(Term::small(4)));
+        assert_eq!(process.get(&Reg::X(1)), Some(Term::intern("back")));
+        assert_eq!(process.code.name(), "b");
+
+        let (process, _rx) = spawned(
+            "{module, a}.
+{call_ext_only, 0, {extfunc, b, missing, 0}}.",
+        );
+        let mut process = process.lock().unwrap();
+        run_to_end(&mut process);
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("undef"))
+        );
+    }
+
+    #[test]
+    fn spawn_limit() {
+        let (tx, _rx) = mpsc::channel();
+        let mut vm = VM {
+            schedulers: Vec::new(),
+            queues: Arc::new(vec![Mutex::new(RunQueue::default())]),
+            shared: Shared::with_process_limit(tx, 2),
+            reductions: Reductions::default(),
+        };
+        let code = vm
+            .load(parse_module(
+                "{module, test}.
+{exports, [{child, 0}]}.
+{move, {x, 0}, test}.
+{move, {x, 1}, child}.
+{move, {x, 2}, []}.
+{bif, spawn, {x, 0}, {x, 1}, {x, 2}, {x, 0}}.
+{ret}.
+{func_info, test, child, 0}.
+{ret}.",
+            ))
+            .unwrap();
+        assert!(vm.spawn(code.clone()).is_some());
+        assert!(vm.spawn(code.clone()).is_some());
+        assert!(vm.spawn(code).is_none());
+
+        // Processes that spawn one too many fail with `system_limit`
+        let process = vm.queues[0].lock().unwrap().pop().unwrap();
+        let mut process = process.lock().unwrap();
+        run_to_end(&mut process);
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("system_limit"))
+        );
+    }
+
+    #[test]
+    fn spawn() {
+        let (process, rx) = spawned(
+            "{module, test}.
+{exports, [{add, 2}]}.
+{move, {x, 0}, test}.
+{move, {x, 1}, add}.
+{move, {x, 2}, [1, {2, 3}]}.
+{bif, spawn, {x, 0}, {x, 1}, {x, 2}, {x, 0}}.
+{ret}.
+{func_info, test, add, 2}.
+{get_tuple_element, {x, 1}, 1, {x, 1}}.
+{add, {x, 0}, {x, 1}, {x, 0}}.",
+        );
+        let mut process = process.lock().unwrap();
+        assert!(process.run());
+        let pid = process.get(&Reg::X(0)).unwrap();
+        assert!(pid.is_pid());
+        // The spawner keeps its own copy of the arguments
+        assert_eq!(
+            process.get(&Reg::X(2)).map(DataObject::from),
+            Some(DataObject::list(
+                vec![
+                    DataObject::Small(1),
+                    DataObject::Tuple(vec![DataObject::Small(2), DataObject::Small(3)])
+                ],
+                DataObject::Nil
+            ))
+        );
+
+        let Ok(VMCmd::Schedule(child)) = rx.try_recv() else {
+            panic!("expected spawned process")
+        };
+        let mut child = child.lock().unwrap();
+        assert_eq!(child.id(), &DataObject::Pid(pid.pid_val()));
+        // Both processes run the same copy of the code
+        assert!(Arc::ptr_eq(&child.code, &process.code));
+        assert!(child.heap.contains(child.get(&Reg::X(1)).unwrap().ptr()));
+        run_to_end(&mut child);
+        assert_eq!(child.get(&Reg::X(0)), Some(Term::small(4)));
+    }
+
+    #[test]
+    fn spawn_fun() {
+        let (process, rx) = spawned(
+            "{module, test}.
+{move, {x, 0}, 5}.
+{make_fun, 1, 0, 1}.
+{bif, spawn_link, {x, 0}, {x, 1}}.
+{ret}.
+{label, 1}.
+{move, {x, 1}, 6}.",
+        );
+        let mut process = process.lock().unwrap();
+        assert!(process.run());
+        assert!(process.get(&Reg::X(0)).unwrap().is_fun());
+        let pid = process.get(&Reg::X(1)).unwrap().pid_val();
+        let me = process.id().expect_pid().clone();
+
+        let Ok(VMCmd::Schedule(child)) = rx.try_recv() else {
+            panic!("expected spawned process")
+        };
+        // The spawner was linked to the child when it finished
+        let Ok(VMCmd::Exit {
+            to, linked: true, ..
+        }) = rx.try_recv()
+        else {
+            panic!("expected exit signal")
+        };
+        assert_eq!(to, pid);
+        let mut child = child.lock().unwrap();
+        assert_eq!(child.pcb_mut().take_links(), vec![me]);
+        // Free variables come first
+        assert_eq!(child.get(&Reg::X(0)), Some(Term::small(5)));
+        run_to_end(&mut child);
+        assert_eq!(child.get(&Reg::X(1)), Some(Term::small(6)));
+    }
+
+    #[test]
+    fn spawn_undef() {
+        let (process, rx) = spawned(
+            "{module, test}.
+{move, {x, 0}, test}.
+{move, {x, 1}, missing}.
+{move, {x, 2}, []}.
+{bif, spawn_link, {x, 0}, {x, 1}, {x, 2}, {x, 0}}.",
+        );
+        let mut process = process.lock().unwrap();
+        assert!(process.run());
+        let pid = process.get(&Reg::X(0)).unwrap().pid_val();
+        // The new process exits right away and takes the linked spawner with it
+        let Ok(VMCmd::Exit {
+            from,
+            reason,
+            linked: true,
+            ..
+        }) = rx.try_recv()
+        else {
+            panic!("expected exit signal")
+        };
+        assert_eq!(from, pid);
+        assert_eq!(reason, DataObject::atom("undef"));
+        assert!(process.vm.procs.lookup(&pid).is_none());
+
+        let process = run_str(
+            "{move, {x, 0}, test}.
+{move, {x, 1}, f}.
+{move, {x, 2}, [a | b]}.
+{bif, spawn, {x, 0}, {x, 1}, {x, 2}, {x, 0}}.",
+        );
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("badarg"))
+        );
+        // Falling through to a func_info means no clause matched
+        let process = run_str("{func_info, test, f, 0}.");
+        assert_eq!(
+            process.pcb().exit_reason(),
+            Some(&DataObject::atom("function_clause"))
+        );
+    }
+
+    #[test]
+    fn registers_per_process() {
+        // Each process counts X0 up to a different limit, bailing out early if its marker in X1
+        // is ever overwritten by the other process.
+        fn counter(marker: i64, limit: i64) -> Vec<Instruction> {
+            vec![
+                Instruction::Move {
+                    dest: Reg::X(0),
+                    src: DataObject::Small(0),
+                },
+                Instruction::Move {
+                    dest: Reg::X(1),
+                    src: DataObject::Small(marker),
+                },
+                Instruction::Move {
+                    dest: Reg::X(2),
+                    src: DataObject::Small(1),
+                },
+                Instruction::Move {
+                    dest: Reg::X(3),
+                    src: DataObject::Small(limit),
+                },
+                Instruction::Move {
+                    dest: Reg::X(4),
+                    src: DataObject::Small(marker),
+                },
+                Instruction::Add {
+                    arg0: Reg::X(0),
+                    arg1: Reg::X(2),
+                    ret: Reg::X(0),
+                },
+                Instruction::IsNe {
+                    lbl: 9,
+                    arg0: Reg::X(1),
+                    arg1: Reg::X(4),
+                },
+                Instruction::IsEq {
+                    lbl: 9,
+                    arg0: Reg::X(0),
+                    arg1: Reg::X(3),
+                },
+                Instruction::Jmp { lbl: 5 },
+            ]
+        }
+
+        // Both processes share a VM and take turns on this thread like they would on a scheduler,
+        // so each slice starts right after the other process wrote the same registers
+        let (tx, _) = mpsc::channel();
+        let vm = Shared::new(tx);
+        let procs: Vec<_> = [(1, 100_000), (2, 200_000)]
+            .into_iter()
+            .map(|(marker, limit)| {
+                let code = Module::new("test", counter(marker, limit));
+                vm.procs
+                    .insert(|num| Process::new(PID::new(0, num), code, vm.clone()))
+                    .unwrap()
+            })
+            .collect();
+        let mut slices = 0;
+        while procs
+            .iter()
+            .any(|proc| !proc.lock().unwrap().pcb().is_exiting())
+        {
+            for proc in &procs {
+                let mut proc = proc.lock().unwrap();
+                if !proc.pcb().is_exiting() {
+                    proc.run();
+                    slices += 1;
+                }
+            }
+        }
+        assert!(slices > 2, "processes were never preempted");
+
+        let results: Vec<_> = procs
+            .iter()
+            .map(|proc| {
+                let proc = proc.lock().unwrap();
+                (
+                    DataObject::from(proc.get(&Reg::X(0)).unwrap()),
+                    DataObject::from(proc.get(&Reg::X(1)).unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (DataObject::Small(100_000), DataObject::Small(1)),
+                (DataObject::Small(200_000), DataObject::Small(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn registers_grow() {
+        let mut process = process_str("{move, {x, 3}, 1}.");
+        assert!(process.registers.written_mut().is_empty());
+        assert_eq!(process.get(&Reg::X(3)), Some(Term::NIL));
+        run_to_end(&mut process);
+        assert_eq!(process.registers.written_mut().len(), 4);
+        assert_eq!(process.get(&Reg::X(3)), Some(Term::small(1)));
+        assert_eq!(process.get(&Reg::X(NUM_X_REGS)), None);
+    }
 }